@@ -0,0 +1,42 @@
+//! Compares hashing a block from scratch for every nonce with hashing only
+//! the nonce on top of the block midstate, as the solvers do.
+//!
+//! Run with `cargo bench -p miner`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use ed25519_dalek::SigningKey;
+use miner::block::{Block, DanceMove};
+
+fn signed_block() -> Block {
+    let mut block = Block::new(vec![0xAB; 32], "benchmark".to_string(), 0, DanceMove::A);
+    block.difficulty = 25;
+    block.sign(&SigningKey::from_bytes(&[7; 32]));
+    block
+}
+
+fn bench_hashing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("nonce attempt");
+
+    let mut block = signed_block();
+    group.bench_function("hash_block", |b| {
+        b.iter(|| {
+            block.nonce = block.nonce.wrapping_add(1);
+            black_box(block.hash_block())
+        })
+    });
+
+    let block = signed_block();
+    let midstate = block.midstate();
+    let mut nonce = 0u64;
+    group.bench_function("midstate", |b| {
+        b.iter(|| {
+            nonce = nonce.wrapping_add(1);
+            black_box(midstate.hash_with_nonce(nonce))
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_hashing);
+criterion_main!(benches);
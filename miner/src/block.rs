@@ -1,9 +1,35 @@
+use crate::consensus::ConsensusRules;
+use crate::consensus::DefaultRules;
+use crate::error::BlockValidationError;
+use crate::merkle::merkle_root;
+use crate::merkle::EMPTY_ROOT;
+use crate::transaction::Transaction;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
 use rand::RngCore;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
 
+/// Network of the blocks created without a chain spec.
+pub const DEFAULT_NETWORK_ID: &str = "dancechain";
+/// Initial difficulty of a chain, committed by its genesis block.
 pub const DIFFICULTY: u32 = 25;
+/// Time we aim for between two blocks, in seconds.
+pub const TARGET_BLOCK_INTERVAL: u64 = 10;
+/// The difficulty is recomputed every `RETARGET_INTERVAL` blocks.
+pub const RETARGET_INTERVAL: u64 = 16;
+/// Maximum change of the difficulty at each retarget, in bits.
+pub const MAX_RETARGET_STEP: u32 = 2;
+/// Number of ancestors whose median timestamp a block must be later than.
+pub const MEDIAN_TIME_BLOCKS: usize = 11;
+/// How far in the future (in seconds) a block timestamp may be.
+pub const MAX_FUTURE_BLOCK_TIME: u64 = 2 * 60;
+/// Maximum number of transactions of a block.
+pub const MAX_BLOCK_TRANSACTIONS: usize = 1000;
 
 #[derive(Default)]
 pub struct BlockHasher {
@@ -35,15 +61,206 @@ pub type BlockHashSet = HashSet<u64, BlockIdHasher>;
 
 #[derive(Clone, Default, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
 pub struct Block {
+    /// Network the block belongs to, so that blocks of different networks
+    /// cannot be mixed even if their chains start from the same genesis.
+    pub network_id: String,
     /// Hash of the parent block
     pub parent_hash: Vec<u8>,
-    /// Miner's (unique) identity. We don't use asymmetric cryptography for this simple exercise.
+    /// Miner's display name. The identity of the miner is `public_key`.
     pub miner: String,
     /// Random value such the hash value of this block is valid.
     pub nonce: u64,
     /// Dancemove chosen by the miner. That's the very strong incentive explaining
     /// why everyone one wants to mine on this blockchain.
     pub dancemove: DanceMove,
+    /// Time at which the block was mined, in seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// Number of leading zero bits the hash of this block must have.
+    pub difficulty: u32,
+    /// Ed25519 public key of the miner. Empty for the genesis block.
+    pub public_key: Vec<u8>,
+    /// Signature by `public_key` of every other field but the nonce, so that
+    /// the block is signed once and not for every nonce attempt.
+    /// Empty for the genesis block.
+    pub signature: Vec<u8>,
+    /// Root of the Merkle tree of the hashes of `transactions`, through
+    /// which the hash of the block covers its body.
+    pub merkle_root: [u8; 32],
+    /// Body of the block. Every other field is the header.
+    pub transactions: Vec<Transaction>,
+}
+
+/// Current time in seconds since the Unix epoch.
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Handle to stop a nonce search from another thread.
+#[derive(Clone, Debug, Default)]
+pub struct CancelHandle(Arc<AtomicBool>);
+
+impl CancelHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Asks the search to stop. It then returns None.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Parameters of the difficulty adjustment of a chain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RetargetParams {
+    /// Time we aim for between two blocks, in seconds.
+    pub block_interval: u64,
+    /// The difficulty is recomputed every `retarget_interval` blocks.
+    pub retarget_interval: u64,
+    /// Maximum change of the difficulty at each retarget, in bits.
+    pub max_retarget_step: u32,
+    /// The difficulty never goes below this one.
+    pub min_difficulty: u32,
+}
+
+impl Default for RetargetParams {
+    fn default() -> Self {
+        RetargetParams {
+            block_interval: TARGET_BLOCK_INTERVAL,
+            retarget_interval: RETARGET_INTERVAL,
+            max_retarget_step: MAX_RETARGET_STEP,
+            min_difficulty: 0,
+        }
+    }
+}
+
+/// Rules a block must follow given its ancestors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChainContext {
+    /// Difficulty the block must commit to.
+    pub difficulty: u32,
+    /// Median timestamp of the last `MEDIAN_TIME_BLOCKS` ancestors. The block
+    /// timestamp must be strictly greater.
+    pub median_time_past: u64,
+}
+
+impl ChainContext {
+    /// Computes the context of a block at height `parent_height + 1` from its
+    /// ancestors, starting with its parent and going back towards the genesis,
+    /// with the default [`RetargetParams`].
+    pub fn from_ancestors<'a, I>(parent_height: u64, ancestors: I) -> Self
+    where
+        I: IntoIterator<Item = &'a Block>,
+    {
+        Self::with_params(&RetargetParams::default(), parent_height, ancestors)
+    }
+
+    /// Computes the context of a block at height `parent_height + 1` from its
+    /// ancestors, starting with its parent and going back towards the genesis.
+    /// At most `max(retarget_interval, MEDIAN_TIME_BLOCKS)` ancestors are read.
+    ///
+    /// The difficulty is the one of the parent, except every
+    /// `retarget_interval` blocks where it is adjusted so that the last
+    /// `retarget_interval` blocks would have been mined at one block every
+    /// `block_interval` seconds.
+    pub fn with_params<'a, I>(params: &RetargetParams, parent_height: u64, ancestors: I) -> Self
+    where
+        I: IntoIterator<Item = &'a Block>,
+    {
+        let interval = params.retarget_interval;
+        let window = (interval as usize).max(MEDIAN_TIME_BLOCKS);
+        let ancestors: Vec<&Block> = ancestors.into_iter().take(window).collect();
+        let Some(parent) = ancestors.first() else {
+            return ChainContext {
+                difficulty: DIFFICULTY,
+                median_time_past: 0,
+            };
+        };
+
+        let height = parent_height + 1;
+        let mut difficulty = parent.difficulty;
+        if interval > 1 && height.is_multiple_of(interval) && ancestors.len() >= interval as usize {
+            let first = ancestors[interval as usize - 1];
+            let actual = parent.timestamp.saturating_sub(first.timestamp);
+            let expected = (interval - 1) * params.block_interval;
+            difficulty = retarget(difficulty, actual, expected, params.max_retarget_step)
+                .max(params.min_difficulty);
+        }
+
+        let mut times: Vec<u64> = ancestors
+            .iter()
+            .take(MEDIAN_TIME_BLOCKS)
+            .map(|block| block.timestamp)
+            .collect();
+        times.sort_unstable();
+
+        ChainContext {
+            difficulty,
+            median_time_past: times[times.len() / 2],
+        }
+    }
+}
+
+/// Adjusts `difficulty` by one bit per factor of two between the `actual` and
+/// `expected` time spans, by at most `max_step` bits.
+fn retarget(difficulty: u32, actual: u64, expected: u64, max_step: u32) -> u32 {
+    let mut actual = actual.max(1);
+    let mut difficulty = difficulty;
+    for _ in 0..max_step {
+        if actual * 2 <= expected {
+            // Blocks came too fast
+            actual *= 2;
+            difficulty = difficulty.saturating_add(1).min(256);
+        } else if actual >= expected * 2 {
+            // Blocks came too slowly
+            actual /= 2;
+            difficulty = difficulty.saturating_sub(1);
+        }
+    }
+    difficulty
+}
+
+/// Number of leading bits set to 0 in `hash`.
+pub fn leading_zeros(hash: &[u8]) -> u32 {
+    let mut zeros = 0;
+    for byte in hash {
+        zeros += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    zeros
+}
+
+/// Amount of work proven by a block hash: 2^(leading zero bits), which is the
+/// expected number of attempts needed to find such a hash.
+/// Saturates at u128::MAX.
+pub fn hash_work(hash: &[u8]) -> u128 {
+    1u128.checked_shl(leading_zeros(hash)).unwrap_or(u128::MAX)
+}
+
+/// SHA-256 state of a block header with everything but the nonce hashed,
+/// built by [`Block::midstate`]. The complete 64 bytes chunks of the header
+/// are compressed once, each attempt only hashes the last chunk and the nonce.
+#[derive(Clone)]
+pub struct Midstate(Sha256);
+
+impl Midstate {
+    /// Hash of the block with `nonce`, equal to [`Block::hash_block`] once the
+    /// nonce is set.
+    #[inline]
+    pub fn hash_with_nonce(&self, nonce: u64) -> [u8; 32] {
+        let mut hasher = self.0.clone();
+        hasher.update(nonce.to_be_bytes());
+        hasher.finalize().into()
+    }
 }
 
 #[derive(Default, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
@@ -56,24 +273,107 @@ pub enum DanceMove {
 }
 
 impl Block {
+    /// Creates a block timestamped now. Its difficulty is set when solving it.
     pub fn new(parent_hash: Vec<u8>, miner: String, nonce: u64, dancemove: DanceMove) -> Self {
         Block {
+            network_id: DEFAULT_NETWORK_ID.to_string(),
             parent_hash,
             miner,
             nonce,
             dancemove,
+            timestamp: unix_time(),
+            difficulty: 0,
+            public_key: Vec::new(),
+            signature: Vec::new(),
+            merkle_root: EMPTY_ROOT,
+            transactions: Vec::new(),
         }
     }
 
+    /// Sets the body of the block and the Merkle root committing to it.
+    /// Must be called before signing the block.
+    pub fn set_transactions(&mut self, transactions: Vec<Transaction>) {
+        self.transactions = transactions;
+        self.merkle_root = self.compute_merkle_root();
+    }
+
+    /// Merkle root of the transactions of the block, which `merkle_root` must
+    /// be equal to.
+    pub fn compute_merkle_root(&self) -> [u8; 32] {
+        let hashes: Vec<[u8; 32]> = self.transactions.iter().map(Transaction::hash).collect();
+        merkle_root(&hashes)
+    }
+
+    /// Bytes covered by the signature: everything but the nonce and the
+    /// signature itself, encoded as in `hash_header`.
+    fn signing_payload(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&(self.network_id.len() as u64).to_be_bytes());
+        payload.extend_from_slice(self.network_id.as_bytes());
+        payload.extend_from_slice(&(self.parent_hash.len() as u64).to_be_bytes());
+        payload.extend_from_slice(&self.parent_hash);
+        payload.extend_from_slice(&(self.miner.len() as u64).to_be_bytes());
+        payload.extend_from_slice(self.miner.as_bytes());
+        payload.push(self.dancemove as u8);
+        payload.extend_from_slice(&self.timestamp.to_be_bytes());
+        payload.extend_from_slice(&self.difficulty.to_be_bytes());
+        payload.extend_from_slice(&self.merkle_root);
+        payload.extend_from_slice(&(self.public_key.len() as u64).to_be_bytes());
+        payload.extend_from_slice(&self.public_key);
+        payload
+    }
+
+    /// Signs the block with the miner's key. Must be called once every
+    /// field but the nonce is set, difficulty included.
+    pub fn sign(&mut self, key: &SigningKey) {
+        self.public_key = key.verifying_key().to_bytes().to_vec();
+        self.signature = key.sign(&self.signing_payload()).to_bytes().to_vec();
+    }
+
+    /// Checks that the block is signed by its `public_key`
+    pub fn verify_signature(&self) -> Result<(), BlockValidationError> {
+        let public_key: [u8; 32] = self
+            .public_key
+            .as_slice()
+            .try_into()
+            .map_err(|_| BlockValidationError::InvalidPublicKey)?;
+        let public_key =
+            VerifyingKey::from_bytes(&public_key).map_err(|_| BlockValidationError::InvalidPublicKey)?;
+        let signature =
+            Signature::from_slice(&self.signature).map_err(|_| BlockValidationError::InvalidSignature)?;
+        public_key
+            .verify(&self.signing_payload(), &signature)
+            .map_err(|_| BlockValidationError::InvalidSignature)
+    }
+
+    /// Feeds the header but the nonce to `hasher`, variable length fields
+    /// prefixed by their length. The transactions are covered by the Merkle
+    /// root.
+    fn hash_header(&self, hasher: &mut Sha256) {
+        hasher.update((self.network_id.len() as u64).to_be_bytes());
+        hasher.update(self.network_id.as_bytes());
+        hasher.update((self.parent_hash.len() as u64).to_be_bytes());
+        hasher.update(&self.parent_hash);
+        hasher.update((self.miner.len() as u64).to_be_bytes());
+        hasher.update(self.miner.as_bytes());
+        hasher.update((self.dancemove as u8).to_be_bytes());
+        hasher.update(self.timestamp.to_be_bytes());
+        hasher.update(self.difficulty.to_be_bytes());
+        hasher.update(self.merkle_root);
+        hasher.update((self.public_key.len() as u64).to_be_bytes());
+        hasher.update(&self.public_key);
+        hasher.update((self.signature.len() as u64).to_be_bytes());
+        hasher.update(&self.signature);
+    }
+
     /// Computes the hash of self
     pub fn hash_block(&self) -> [u8; 32] {
         let mut hasher = Sha256::new();
         
-        // Adding the block's data to the hasher
-        hasher.update(&self.parent_hash);
-        hasher.update(self.miner.as_bytes());
-        hasher.update(&self.nonce.to_be_bytes());
-        hasher.update(&(self.dancemove as u8).to_be_bytes());
+        // Adding the block's data to the hasher. The nonce comes last, see
+        // `Block::midstate`.
+        self.hash_header(&mut hasher);
+        hasher.update(self.nonce.to_be_bytes());
         
         // hash result
         let result = hasher.finalize();
@@ -84,15 +384,28 @@ impl Block {
         hash
     }
 
+    /// Hashing state of the block with every field but the nonce absorbed,
+    /// to hash it cheaply for many nonces when mining.
+    pub fn midstate(&self) -> Midstate {
+        let mut hasher = Sha256::new();
+        self.hash_header(&mut hasher);
+        Midstate(hasher)
+    }
+
     /// Solves the block finding a nonce that hashes the block to
     /// a hash value starting with `difficulty` bits set to 0. Returns the
     /// hash value of the block stored in a Vec.
+    /// The block commits to `difficulty`: set it before signing the block,
+    /// as the difficulty of a signed block cannot change anymore. Returns
+    /// None without searching if a signed block commits to another one.
     pub fn solve_block<R: RngCore>(
         &mut self,
         rng: &mut R,
         difficulty: u32,
         max_iteration: Option<u64>,
     ) -> Option<Vec<u8>> {
+        self.commit_difficulty(difficulty)?;
+        let midstate = self.midstate();
         let mut iterations = 0;
         let max_iter = max_iteration.unwrap_or(u64::MAX);
         
@@ -101,7 +414,7 @@ impl Block {
             self.nonce = rand::Rng::gen(rng);
             
             // Hashing the block with the current nonce
-            let hash = self.hash_block();
+            let hash = midstate.hash_with_nonce(self.nonce);
             
             // Checking if the hash meets the difficulty requirement
             if self.pow_check(&hash, difficulty) {
@@ -114,6 +427,92 @@ impl Block {
         None
     }
 
+    /// Same as [`Block::solve_block`], splitting the search across `threads`
+    /// worker threads. The nonce space is walked from a random start, worker
+    /// `i` trying the nonces `start + i`, `start + i + threads`, and so on.
+    /// All the workers stop as soon as one of them finds a solution.
+    /// `max_iteration` bounds the total number of attempts of all workers.
+    pub fn solve_block_parallel<R: RngCore>(
+        &mut self,
+        rng: &mut R,
+        difficulty: u32,
+        max_iteration: Option<u64>,
+        threads: usize,
+    ) -> Option<Vec<u8>> {
+        self.solve_block_cancellable(rng, difficulty, max_iteration, threads, &CancelHandle::new())
+    }
+
+    /// Same as [`Block::solve_block_parallel`], returning None as soon as
+    /// `cancel` is cancelled.
+    pub fn solve_block_cancellable<R: RngCore>(
+        &mut self,
+        rng: &mut R,
+        difficulty: u32,
+        max_iteration: Option<u64>,
+        threads: usize,
+        cancel: &CancelHandle,
+    ) -> Option<Vec<u8>> {
+        self.commit_difficulty(difficulty)?;
+        self.search_nonce(rng, difficulty, max_iteration, threads, cancel)
+    }
+
+    /// Sets the difficulty the block commits to. Fails if the block is
+    /// signed for another difficulty, as its signature would not verify.
+    fn commit_difficulty(&mut self, difficulty: u32) -> Option<()> {
+        if !self.signature.is_empty() && self.difficulty != difficulty {
+            return None;
+        }
+        self.difficulty = difficulty;
+        Some(())
+    }
+
+    /// Same as [`Block::solve_block_cancellable`], looking for a hash with
+    /// `target` leading zero bits without changing the difficulty the block
+    /// commits to, such as pool shares easier than the block.
+    pub fn search_nonce<R: RngCore>(
+        &mut self,
+        rng: &mut R,
+        target: u32,
+        max_iteration: Option<u64>,
+        threads: usize,
+        cancel: &CancelHandle,
+    ) -> Option<Vec<u8>> {
+        let threads = threads.max(1) as u64;
+        let start: u64 = rand::Rng::gen(rng);
+        let max_iter = max_iteration.unwrap_or(u64::MAX);
+        let found = AtomicBool::new(false);
+        let solution = Mutex::new(None);
+        let midstate = self.midstate();
+
+        thread::scope(|scope| {
+            for worker in 0..threads {
+                // Attempts of this worker, so that they sum up to max_iter
+                let attempts = max_iter / threads + u64::from(worker < max_iter % threads);
+                let block = &*self;
+                let (found, solution, midstate) = (&found, &solution, &midstate);
+                scope.spawn(move || {
+                    let mut nonce = start.wrapping_add(worker);
+                    for _ in 0..attempts {
+                        if found.load(Ordering::Relaxed) || cancel.is_cancelled() {
+                            return;
+                        }
+                        let hash = midstate.hash_with_nonce(nonce);
+                        if block.pow_check(&hash, target) {
+                            found.store(true, Ordering::Relaxed);
+                            solution.lock().unwrap().get_or_insert((nonce, hash));
+                            return;
+                        }
+                        nonce = nonce.wrapping_add(threads);
+                    }
+                });
+            }
+        });
+
+        let (nonce, hash) = solution.into_inner().unwrap()?;
+        self.nonce = nonce;
+        Some(hash.to_vec())
+    }
+
     /// Checks if the proof of work is correct
     pub fn pow_check(&self, hash: &[u8], difficulty: u32) -> bool {
         if difficulty == 0 {
@@ -149,36 +548,22 @@ impl Block {
         true
     }
 
-    #[allow(dead_code)]
-    pub fn is_block_valid(&self, difficulty: u32) -> Result<(), &'static str> {
-        // Checking if miner name is valid
-        if self.miner == "changemeyoufool" || (self.miner == "Genesis" && !self.parent_hash.is_empty()) {
-            return Err("Invalid miner name");
-        }
-        
-        // Checking if dancemove is valid (1-4)
-        let dance_value = self.dancemove as u8;
-        if dance_value < 1 || dance_value > 4 {
-            return Err("Invalid dance move");
-        }
-        
-        // Checking proof of work
-        let hash = self.hash_block();
-        if !self.pow_check(&hash, difficulty) {
-            return Err("Invalid proof of work");
-        }
-        
-        Ok(())
+    /// Checks that do not depend on the ancestors of the block, under the
+    /// default rules. See [`ConsensusRules::validate_header`].
+    pub fn is_block_sane(&self, min_difficulty: u32) -> Result<(), BlockValidationError> {
+        DefaultRules.validate_header(self, min_difficulty)
     }
 
+    /// Checks the block against the rules given by its ancestors, under the
+    /// default rules. See [`ConsensusRules::validate_contextual`].
+    pub fn is_block_valid(&self, context: &ChainContext) -> Result<(), BlockValidationError> {
+        DefaultRules.validate_contextual(self, context)
+    }
+
+    /// Whether the block can start a chain under the default rules. See
+    /// [`ConsensusRules::is_genesis`].
     pub fn is_genesis(&self, difficulty: u32) -> bool {
-        if self.parent_hash.is_empty() && self.miner == "Genesis" {
-            // checking if the hash of the genesis block is valid
-            // and meets the difficulty requirement
-            let hash = self.hash_block();
-            return self.pow_check(&hash, difficulty);
-        }
-        false
+        DefaultRules.is_genesis(self, difficulty)
     }
 }
 
@@ -219,17 +604,11 @@ impl crate::simpletree::Parenting for Block {
 mod tests {
     use super::*;
     use rand::rngs::StdRng;
-    use rand::Rng;
     use rand::SeedableRng;
 
     #[test]
     fn test_pow_check() {
-        let block = Block {
-            parent_hash: vec![],
-            miner: "test".to_string(),
-            nonce: 0,
-            dancemove: DanceMove::C,
-        };
+        let block = Block::new(vec![], "test".to_string(), 0, DanceMove::C);
 
         // Test case where hash has sufficient leading zeros
         let hash_with_zeros = vec![0x00, 0x00, 0x00, 0xFF];
@@ -243,14 +622,20 @@ mod tests {
         assert!(block.pow_check(&hash_without_zeros, 0));
     }
 
+    #[test]
+    fn test_hash_work() {
+        assert_eq!(leading_zeros(&[0xFF, 0x00]), 0);
+        assert_eq!(leading_zeros(&[0x00, 0x10, 0x00]), 11);
+        assert_eq!(leading_zeros(&[0x00, 0x00]), 16);
+
+        assert_eq!(hash_work(&[0x80]), 1);
+        assert_eq!(hash_work(&[0x00, 0x10]), 1 << 11);
+        assert_eq!(hash_work(&[0x00; 32]), u128::MAX);
+    }
+
     #[test]
     fn test_solve_block() {
-        let mut block = Block {
-            parent_hash: vec![],
-            miner: "test".to_string(),
-            nonce: 0,
-            dancemove: DanceMove::Y,
-        };
+        let mut block = Block::new(vec![], "test".to_string(), 0, DanceMove::Y);
         // Notes for students:
         // Use a seeded Rng for deterministic testing
         // Remember from class 04; a PRG is deterministic and
@@ -276,6 +661,61 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_midstate() {
+        let mut block = Block::new(vec![3; 32], "a miner with a long name".to_string(), 0, DanceMove::C);
+        block.difficulty = 12;
+        block.sign(&test_key());
+
+        let midstate = block.midstate();
+        for nonce in [0, 1, 42, u64::MAX] {
+            block.nonce = nonce;
+            assert_eq!(midstate.hash_with_nonce(nonce), block.hash_block());
+        }
+
+        // Fields are length prefixed, moving bytes between them changes the hash
+        let mut a = Block::new(vec![1, 2], "ab".to_string(), 0, DanceMove::C);
+        let mut b = Block::new(vec![1], "\u{2}ab".to_string(), 0, DanceMove::C);
+        b.timestamp = a.timestamp;
+        assert_ne!(a.hash_block(), b.hash_block());
+        a.parent_hash = vec![1];
+        a.miner = "\u{2}ab".to_string();
+        assert_eq!(a.hash_block(), b.hash_block());
+    }
+
+    #[test]
+    fn test_solve_block_parallel() {
+        let mut block = Block::new(vec![], "test".to_string(), 0, DanceMove::Y);
+        let mut rng = StdRng::seed_from_u64(42);
+
+        for threads in [1, 4] {
+            let hash = block.solve_block_parallel(&mut rng, 12, None, threads).unwrap();
+            assert_eq!(hash, block.hash_block().to_vec());
+            assert!(block.pow_check(&hash, 12));
+            assert_eq!(block.difficulty, 12);
+        }
+
+        // Out of attempts
+        assert!(block.solve_block_parallel(&mut rng, 200, Some(1000), 4).is_none());
+        assert!(block.solve_block_parallel(&mut rng, 1, Some(0), 4).is_none());
+    }
+
+    #[test]
+    fn test_cancel_solve() {
+        let mut block = Block::new(vec![], "test".to_string(), 0, DanceMove::Y);
+        let mut rng = StdRng::seed_from_u64(42);
+        let cancel = CancelHandle::new();
+
+        let canceller = cancel.clone();
+        let handle = thread::spawn(move || {
+            thread::sleep(std::time::Duration::from_millis(50));
+            canceller.cancel();
+        });
+        // Impossible to solve, only the cancellation stops the search
+        assert!(block.solve_block_cancellable(&mut rng, 256, None, 2, &cancel).is_none());
+        handle.join().unwrap();
+    }
+
     #[test]
     fn test_new_genesis() {
         let mut genesis = Block::new(Vec::new(), "Genesis".to_string(), 42, DanceMove::C);
@@ -284,4 +724,169 @@ mod tests {
         genesis.solve_block(&mut rng, 10, None).unwrap();
         assert!(genesis.is_genesis(10));
     }
-}
\ No newline at end of file
+
+    fn test_key() -> SigningKey {
+        SigningKey::from_bytes(&[7; 32])
+    }
+
+    #[test]
+    fn test_signature() {
+        let mut block = Block::new(vec![1], "test".to_string(), 0, DanceMove::A);
+        assert!(block.verify_signature().is_err());
+        assert!(block.is_block_sane(0).is_err());
+
+        block.sign(&test_key());
+        assert!(block.verify_signature().is_ok());
+        assert!(block.is_block_sane(0).is_ok());
+
+        // The nonce is not signed so that it can be changed while mining
+        block.nonce = 42;
+        assert!(block.verify_signature().is_ok());
+
+        // Anything else is
+        block.miner = "someone else".to_string();
+        assert!(block.verify_signature().is_err());
+        block.miner = "test".to_string();
+        block.public_key = SigningKey::from_bytes(&[8; 32]).verifying_key().to_bytes().to_vec();
+        assert!(block.verify_signature().is_err());
+    }
+
+    fn chain(timestamps: &[u64], difficulty: u32) -> Vec<Block> {
+        timestamps
+            .iter()
+            .map(|&timestamp| Block {
+                timestamp,
+                difficulty,
+                ..Block::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_retarget() {
+        // Ancestors from the parent back; the new block is at a retarget height
+        let parent_height = RETARGET_INTERVAL - 1;
+        let on_time: Vec<u64> = (0..RETARGET_INTERVAL).rev().map(|i| i * TARGET_BLOCK_INTERVAL).collect();
+        let fast: Vec<u64> = (0..RETARGET_INTERVAL).rev().collect();
+        let slow: Vec<u64> = (0..RETARGET_INTERVAL).rev().map(|i| i * TARGET_BLOCK_INTERVAL * 3).collect();
+
+        let context = ChainContext::from_ancestors(parent_height, &chain(&on_time, 20));
+        assert_eq!(context.difficulty, 20);
+        let context = ChainContext::from_ancestors(parent_height, &chain(&fast, 20));
+        assert_eq!(context.difficulty, 20 + MAX_RETARGET_STEP);
+        let context = ChainContext::from_ancestors(parent_height, &chain(&slow, 20));
+        assert_eq!(context.difficulty, 19);
+
+        // No retarget between retarget heights
+        let context = ChainContext::from_ancestors(parent_height + 1, &chain(&fast, 20));
+        assert_eq!(context.difficulty, 20);
+    }
+
+    #[test]
+    fn test_retarget_params() {
+        let params = RetargetParams {
+            block_interval: 60,
+            retarget_interval: 4,
+            max_retarget_step: 1,
+            min_difficulty: 20,
+        };
+        // 10 seconds between blocks instead of 60
+        let fast = chain(&[30, 20, 10, 0], 20);
+        assert_eq!(ChainContext::with_params(&params, 3, &fast).difficulty, 21);
+        assert_eq!(ChainContext::with_params(&params, 4, &fast).difficulty, 20);
+
+        // Never below the minimum
+        let slow = chain(&[3000, 2000, 1000, 0], 20);
+        assert_eq!(ChainContext::with_params(&params, 3, &slow).difficulty, 20);
+    }
+
+    #[test]
+    fn test_median_time_past() {
+        let ancestors = chain(&[50, 10, 40, 20, 30], 0);
+        let context = ChainContext::from_ancestors(4, &ancestors);
+        assert_eq!(context.median_time_past, 30);
+
+        let key = test_key();
+        let mut block = Block::new(vec![1], "test".to_string(), 0, DanceMove::A);
+        block.difficulty = context.difficulty;
+        block.timestamp = 30;
+        block.sign(&key);
+        assert_eq!(
+            block.is_block_valid(&context),
+            Err(BlockValidationError::TimestampTooEarly {
+                timestamp: 30,
+                median_time_past: 30
+            })
+        );
+        block.timestamp = 31;
+        block.sign(&key);
+        assert!(block.is_block_valid(&context).is_ok());
+        block.timestamp = unix_time() + MAX_FUTURE_BLOCK_TIME + 60;
+        block.sign(&key);
+        assert!(block.is_block_valid(&context).is_err());
+    }
+
+    #[test]
+    fn test_transactions() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let transfer = |amount| Transaction {
+            from: vec![1; 32],
+            to: vec![2; 32],
+            amount,
+            ..Transaction::default()
+        };
+        let mut block = Block::new(vec![1], "test".to_string(), 0, DanceMove::A);
+        block.difficulty = 8;
+        block.set_transactions(vec![transfer(1), transfer(2)]);
+        assert_ne!(block.merkle_root, EMPTY_ROOT);
+        block.sign(&test_key());
+        block.solve_block(&mut rng, 8, None).unwrap();
+        assert!(block.is_block_sane(8).is_ok());
+
+        // The hash only covers the header, which commits to the body
+        let hash = block.hash_block();
+        block.transactions[1].amount = 3;
+        assert_eq!(block.hash_block(), hash);
+        assert_eq!(block.is_block_sane(8), Err(BlockValidationError::InvalidMerkleRoot));
+    }
+
+    #[test]
+    fn test_committed_difficulty() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut block = Block::new(vec![1], "test".to_string(), 0, DanceMove::A);
+        block.difficulty = 8;
+        block.sign(&test_key());
+        block.solve_block(&mut rng, 8, None).unwrap();
+        assert_eq!(block.difficulty, 8);
+        assert!(block.is_block_sane(8).is_ok());
+        assert_eq!(
+            block.is_block_sane(9),
+            Err(BlockValidationError::DifficultyTooLow {
+                difficulty: 8,
+                min_difficulty: 9
+            })
+        );
+
+        let context = ChainContext {
+            difficulty: 9,
+            median_time_past: 0,
+        };
+        assert!(block.is_block_valid(&context).is_err());
+    }
+
+    #[test]
+    fn test_signed_difficulty_mismatch() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut block = Block::new(vec![1], "test".to_string(), 0, DanceMove::A);
+        block.difficulty = 8;
+        block.sign(&test_key());
+        let signed = block.clone();
+
+        // The signature covers the difficulty, which cannot change
+        assert_eq!(block.solve_block(&mut rng, 9, None), None);
+        assert_eq!(block.solve_block_parallel(&mut rng, 1, None, 2), None);
+        assert_eq!(block, signed);
+        assert!(block.solve_block(&mut rng, 8, None).is_some());
+        assert!(block.verify_signature().is_ok());
+    }
+}
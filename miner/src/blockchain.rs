@@ -0,0 +1,962 @@
+use crate::block::Block;
+use crate::block::ChainContext;
+use crate::consensus::ConsensusRules;
+use crate::consensus::DefaultRules;
+use crate::error::BlockValidationError;
+use crate::ledger::Ledger;
+use crate::simpletree::BlockTree;
+use crate::simpletree::Parenting;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Maximum number of blocks kept in the orphan pool.
+pub const MAX_ORPHANS: usize = 1024;
+/// Time after which a block still waiting for its parent is dropped.
+pub const ORPHAN_EXPIRY: Duration = Duration::from_secs(10 * 60);
+
+/// What happened to a block given to [`Blockchain::add_block`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockStatus {
+    /// The block, and the orphans that were waiting on it, were connected to
+    /// the tree. Carries their hashes, parents before children.
+    Connected(Vec<Vec<u8>>),
+    /// The parent of the block is unknown; it waits in the orphan pool.
+    Orphaned,
+    /// The block is already known.
+    Duplicate,
+}
+
+/// Blocks waiting for their parent, bounded in number and age.
+#[derive(Debug, Default)]
+struct OrphanPool {
+    /// Orphans by hash, with their arrival time.
+    blocks: HashMap<Vec<u8>, (Instant, Block)>,
+    /// Hashes of the orphans, indexed by parent hash.
+    by_parent: HashMap<Vec<u8>, Vec<Vec<u8>>>,
+    /// Arrival order, oldest first. May refer to orphans that are gone.
+    arrivals: VecDeque<(Instant, Vec<u8>)>,
+}
+
+impl OrphanPool {
+    fn contains(&self, hash: &[u8]) -> bool {
+        self.blocks.contains_key(hash)
+    }
+
+    fn insert(&mut self, hash: Vec<u8>, block: Block, now: Instant) {
+        self.by_parent
+            .entry(block.parent_hash.clone())
+            .or_default()
+            .push(hash.clone());
+        self.arrivals.push_back((now, hash.clone()));
+        self.blocks.insert(hash, (now, block));
+        self.prune(now);
+    }
+
+    /// Removes and returns the orphans waiting on `parent_hash`
+    fn take_children(&mut self, parent_hash: &[u8]) -> Vec<(Vec<u8>, Block)> {
+        self.by_parent
+            .remove(parent_hash)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|hash| {
+                let (_, block) = self.blocks.remove(&hash)?;
+                Some((hash, block))
+            })
+            .collect()
+    }
+
+    /// Drops the expired orphans, then the oldest ones if the pool is full
+    fn prune(&mut self, now: Instant) {
+        while let Some((arrival, hash)) = self.arrivals.front() {
+            let expired = now.duration_since(*arrival) >= ORPHAN_EXPIRY;
+            if !expired && self.blocks.len() <= MAX_ORPHANS {
+                break;
+            }
+            // Only drop the orphan if this entry is its latest arrival
+            if self.blocks.get(hash).is_some_and(|(t, _)| t == arrival) {
+                let (_, block) = self.blocks.remove(hash).unwrap();
+                if let Some(siblings) = self.by_parent.get_mut(block.parent_hash()) {
+                    siblings.retain(|sibling| sibling != hash);
+                    if siblings.is_empty() {
+                        self.by_parent.remove(block.parent_hash());
+                    }
+                }
+            }
+            self.arrivals.pop_front();
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &Block> {
+        self.blocks.values().map(|(_, block)| block)
+    }
+}
+
+/// A tree of valid blocks rooted at a genesis block, with the orphan pool.
+/// Blocks are validated, and the best tip chosen, by its consensus rules.
+#[derive(Debug)]
+pub struct Blockchain {
+    /// The rules blocks are validated with.
+    rules: Arc<dyn ConsensusRules>,
+    /// The blockchain is stored by block hash, each block knowing the
+    /// hashes of its children.
+    blocks: BlockTree<Block>,
+    /// Blocks waiting for their parent.
+    orphans: OrphanPool,
+    /// Work accumulated from the genesis up to each block, included.
+    chain_work: HashMap<Vec<u8>, u128>,
+    /// Height of each block, the genesis being at height 0.
+    heights: HashMap<Vec<u8>, u64>,
+    /// Hash of the tip with the most accumulated work.
+    best_tip: Vec<u8>,
+    /// Accounts along the best chain. It moves to other branches while
+    /// validating their blocks, and back to the best tip afterwards.
+    ledger: Ledger,
+}
+
+impl Blockchain {
+    pub fn new_from_genesis(genesis: Block) -> Self {
+        Self::with_rules(genesis, Arc::new(DefaultRules))
+    }
+
+    /// Creates a blockchain validating blocks with the given rules.
+    pub fn with_rules(genesis: Block, rules: Arc<dyn ConsensusRules>) -> Self {
+        let blocks = BlockTree::new(genesis);
+        let best_tip = blocks.root_hash().to_vec();
+        let mut chain_work = HashMap::new();
+        chain_work.insert(best_tip.clone(), rules.block_work(&best_tip));
+        let mut heights = HashMap::new();
+        heights.insert(best_tip.clone(), 0);
+        let mut ledger = Ledger::default();
+        ledger
+            .apply_block(best_tip.clone(), blocks.root(), rules.block_reward(0))
+            .expect("the genesis block has no transactions");
+        Blockchain {
+            rules,
+            blocks,
+            orphans: OrphanPool::default(),
+            chain_work,
+            heights,
+            best_tip,
+            ledger,
+        }
+    }
+
+    /// Creates a new Blockchain from the provided genesis
+    /// block and vector of valid blocks.
+    /// Blocks whose parent is unknown are returned.
+    pub fn new_from_genesis_and_vec(
+        genesis: Block,
+        blocks: Vec<Block>,
+    ) -> (Self, Vec<Block>) {
+        let mut blockchain = Self::new_from_genesis(genesis);
+        blockchain.add_blocks(blocks);
+        let remaining_blocks = blockchain.orphans().cloned().collect();
+        (blockchain, remaining_blocks)
+    }
+
+    /// Adds the blocks we have not seen yet to the blockchain, dropping the
+    /// invalid ones. Returns the hashes of the blocks that were connected to
+    /// the tree by this call, parents before children.
+    pub fn add_blocks(&mut self, blocks: Vec<Block>) -> Vec<Vec<u8>> {
+        let mut connected = Vec::new();
+        for block in blocks {
+            if let Ok(BlockStatus::Connected(hashes)) = self.add_block(block) {
+                connected.extend(hashes);
+            }
+        }
+        connected
+    }
+
+    /// Adds a block to the blockchain.
+    /// A block whose parent is unknown is kept in the orphan pool until the
+    /// parent shows up, as long as it is signed and its proof of work
+    /// matches the difficulty it commits to. It is fully validated against
+    /// its ancestors once connected; orphans that turn out to be invalid
+    /// are dropped.
+    ///
+    /// Transactions are checked against the ledger of the parent of the
+    /// block, whichever branch it is on.
+    pub fn add_block(&mut self, block: Block) -> Result<BlockStatus, BlockValidationError> {
+        self.add_block_with(block, |_| Ok(()))
+    }
+
+    /// Same as [`Blockchain::add_block`], calling `store` with each valid
+    /// block right before connecting it, such as to write it to disk first.
+    /// A block `store` fails on is not connected: the error is returned if
+    /// it is `block`, and an orphan waiting on it is dropped, to be
+    /// connected if it comes again.
+    pub fn add_block_with<E, F>(&mut self, block: Block, mut store: F) -> Result<BlockStatus, E>
+    where
+        E: From<BlockValidationError>,
+        F: FnMut(&Block) -> Result<(), E>,
+    {
+        let now = Instant::now();
+        self.orphans.prune(now);
+
+        let hash = block.hash();
+        if self.blocks.contains(&hash) || self.orphans.contains(&hash) {
+            return Ok(BlockStatus::Duplicate);
+        }
+        if block.parent_hash.is_empty() {
+            // There is a single genesis block: our root
+            return Err(BlockValidationError::InvalidGenesis.into());
+        }
+        if !self.blocks.contains(block.parent_hash()) {
+            self.rules.validate_header(&block, 0)?;
+            self.orphans.insert(hash, block, now);
+            return Ok(BlockStatus::Orphaned);
+        }
+        self.validate(&block)?;
+
+        // Insert the block, then every orphan that was waiting on it
+        let mut connected = Vec::new();
+        let mut ready = vec![(hash, block)];
+        while let Some((hash, block)) = ready.pop() {
+            if let Err(e) = store(&block) {
+                if connected.is_empty() {
+                    let best_tip = self.best_tip.clone();
+                    self.move_ledger(&best_tip);
+                    return Err(e);
+                }
+                continue;
+            }
+            self.connect(hash.clone(), block);
+            for (child_hash, child) in self.orphans.take_children(&hash) {
+                if self.validate(&child).is_ok() {
+                    ready.push((child_hash, child));
+                }
+            }
+            connected.push(hash);
+        }
+        let best_tip = self.best_tip.clone();
+        self.move_ledger(&best_tip);
+        Ok(BlockStatus::Connected(connected))
+    }
+
+    /// Checks a block whose parent is in the tree
+    fn validate(&mut self, block: &Block) -> Result<(), BlockValidationError> {
+        let context = self
+            .next_context(block.parent_hash())
+            .ok_or(BlockValidationError::UnknownParent)?;
+        self.rules.validate_contextual(block, &context)?;
+
+        let height = self.heights[block.parent_hash()] + 1;
+        self.move_ledger(block.parent_hash());
+        self.ledger
+            .apply_block(block.hash(), block, self.rules.block_reward(height))?;
+        self.ledger.revert_block();
+        Ok(())
+    }
+
+    /// Moves the ledger to the given block of the tree, reverting the blocks
+    /// it applied down to their common ancestor, then applying the blocks up
+    /// to the target.
+    fn move_ledger(&mut self, target: &[u8]) {
+        let mut to_apply = Vec::new();
+        let mut hash = target;
+        let mut height = self.heights[hash];
+        while self.ledger.hash_at(height) != Some(hash) {
+            to_apply.push((hash, height));
+            hash = self.blocks.get(hash).expect("ancestors are in the tree").parent_hash();
+            height -= 1;
+        }
+        while self.ledger.height() > Some(height) {
+            self.ledger.revert_block();
+        }
+        for (hash, height) in to_apply.into_iter().rev() {
+            let block = self.blocks.get(hash).expect("ancestors are in the tree");
+            self.ledger
+                .apply_block(hash.to_vec(), block, self.rules.block_reward(height))
+                .expect("connected blocks were validated against their parent");
+        }
+    }
+
+    /// Inserts a block whose parent is in the tree
+    fn connect(&mut self, hash: Vec<u8>, block: Block) {
+        let work = self.chain_work[block.parent_hash()].saturating_add(self.rules.block_work(&hash));
+        let height = self.heights[block.parent_hash()] + 1;
+        self.blocks.insert_with_hash(hash.clone(), block);
+        self.update_best_tip(&hash, work);
+        self.chain_work.insert(hash.clone(), work);
+        self.heights.insert(hash, height);
+    }
+
+    /// Whether the block with the given hash is in the tree
+    pub fn contains(&self, hash: &[u8]) -> bool {
+        self.blocks.contains(hash)
+    }
+
+    /// Get the block with the given hash from the tree
+    pub fn get(&self, hash: &[u8]) -> Option<&Block> {
+        self.blocks.get(hash)
+    }
+
+    /// The genesis block
+    pub fn genesis(&self) -> &Block {
+        self.blocks.root()
+    }
+
+    /// Whether the block with the given hash waits for its parent
+    pub fn is_orphan(&self, hash: &[u8]) -> bool {
+        self.orphans.contains(hash)
+    }
+
+    /// Blocks received whose ancestry does not reach the genesis yet
+    pub fn orphans(&self) -> impl Iterator<Item = &Block> {
+        self.orphans.iter()
+    }
+
+    /// The rules blocks are validated with
+    pub fn rules(&self) -> &Arc<dyn ConsensusRules> {
+        &self.rules
+    }
+
+    /// Applies the fork choice rule to a new tip
+    fn update_best_tip(&mut self, hash: &[u8], work: u128) {
+        let best_work = self.chain_work[&self.best_tip];
+        if self.rules.is_better_tip(hash, work, &self.best_tip, best_work) {
+            self.best_tip = hash.to_vec();
+        }
+    }
+
+    /// Hash of the tip with the most accumulated work
+    pub fn best_tip(&self) -> &[u8] {
+        &self.best_tip
+    }
+
+    /// Accounts after the best chain
+    pub fn ledger(&self) -> &Ledger {
+        &self.ledger
+    }
+
+    /// Height of the given block, the genesis being at height 0
+    pub fn height(&self, hash: &[u8]) -> Option<u64> {
+        self.heights.get(hash).copied()
+    }
+
+    /// Whether the given block is on the best chain
+    pub fn is_on_best_chain(&self, hash: &[u8]) -> bool {
+        self.height(hash)
+            .is_some_and(|height| self.ledger.hash_at(height) == Some(hash))
+    }
+
+    /// Work accumulated from the genesis up to the given block
+    pub fn chain_work(&self, hash: &[u8]) -> Option<u128> {
+        self.chain_work.get(hash).copied()
+    }
+
+    /// Iterates from the given block back to the genesis, both included
+    pub fn ancestors<'a>(&'a self, hash: &[u8]) -> impl Iterator<Item = &'a Block> {
+        std::iter::successors(self.blocks.get(hash), move |block| {
+            self.blocks.get(block.parent_hash())
+        })
+    }
+
+    /// Rules a new child of the given block has to follow
+    pub fn next_context(&self, parent_hash: &[u8]) -> Option<ChainContext> {
+        let height = *self.heights.get(parent_hash)?;
+        Some(self.rules.next_context(height, &mut self.ancestors(parent_hash)))
+    }
+
+    /// Get the best chain, from the genesis to the best tip
+    pub fn best_chain(&self) -> Vec<Block> {
+        let mut chain: Vec<Block> = self.ancestors(&self.best_tip).cloned().collect();
+        chain.reverse();
+        chain
+    }
+
+    fn print_tree(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        hash: &[u8],
+        prefixes: &mut Vec<bool>,
+    ) -> fmt::Result {
+        // Print the current node
+        if !prefixes.is_empty() {
+            // Print connecting lines from parent
+            for &is_last in &prefixes[..prefixes.len() - 1] {
+                write!(f, "{}", if is_last { "    " } else { "│   " })?;
+            }
+
+            // Print the appropriate connector
+            let is_last = *prefixes.last().unwrap();
+            write!(f, "{}", if is_last { "└── " } else { "├── " })?;
+        }
+
+        // Print the block info
+        let block = self.blocks.get(hash).unwrap();
+        writeln!(f, "{} (nonce: {})", block.miner, block.nonce)?;
+
+        // Recursively print children
+        let children = self.blocks.children(hash);
+        let child_count = children.len();
+        for (i, child) in children.iter().enumerate() {
+            prefixes.push(i == child_count - 1); // true if this is the last child
+            self.print_tree(f, child, prefixes)?;
+            prefixes.pop();
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Blockchain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.print_tree(f, self.blocks.root_hash(), &mut Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::hash_work;
+    use crate::block::leading_zeros;
+    use crate::block::BlockHashSet;
+    use crate::block::DanceMove;
+    use crate::error::TransactionError;
+    use crate::ledger::Account;
+    use crate::transaction::Transaction;
+    use ed25519_dalek::SigningKey;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn test_key() -> SigningKey {
+        SigningKey::from_bytes(&[7; 32])
+    }
+
+    fn create_test_block(parent_hash: &[u8], nonce_init: u64, miner: &str) -> Block {
+        let mut block = Block::new(
+            parent_hash.to_vec(),
+            miner.to_string(),
+            nonce_init,
+            DanceMove::Y,
+        );
+        // Increasing timestamps to follow the median time past rule
+        block.timestamp = nonce_init + 1;
+        block.sign(&test_key());
+        block
+    }
+
+    /// Children of the block with the given hash, in insertion order
+    fn children<'a>(blockchain: &'a Blockchain, hash: &[u8]) -> Vec<&'a Block> {
+        blockchain
+            .blocks
+            .children(hash)
+            .iter()
+            .map(|child| blockchain.blocks.get(child).unwrap())
+            .collect()
+    }
+
+    fn root_children(blockchain: &Blockchain) -> Vec<&Block> {
+        children(blockchain, blockchain.blocks.root_hash())
+    }
+
+    #[test]
+    fn test_empty_blocks() {
+        let genesis = create_test_block(&[], 0, "Genesis");
+        let (blockchain, _) =
+            Blockchain::new_from_genesis_and_vec(genesis, vec![]);
+
+        assert_eq!(root_children(&blockchain).len(), 0);
+    }
+
+    #[test]
+    fn test_single_valid_block() {
+        let genesis = create_test_block(&[], 0, "Genesis");
+        let genesis_hash = genesis.hash_block().to_vec();
+
+        let block1 = create_test_block(&genesis_hash, 42, "miner1");
+        let mut blockids = BlockHashSet::default();
+        blockids.insert(42);
+        let (blockchain, _) =
+            Blockchain::new_from_genesis_and_vec(genesis, vec![block1]);
+        assert_eq!(blockids.len(), 1);
+
+        let root = root_children(&blockchain);
+        assert_eq!(root.len(), 1);
+        assert_eq!(root[0].miner, "miner1");
+    }
+
+    #[test]
+    fn test_multiple_levels() {
+        let genesis = create_test_block(&[], 0, "Genesis");
+        let genesis_hash = genesis.hash_block().to_vec();
+
+        let block1 = create_test_block(&genesis_hash, 42, "miner1");
+        let block1_hash = block1.hash_block().to_vec();
+
+        let block2 = create_test_block(&genesis_hash, 43, "miner2");
+        let block3 = create_test_block(&block1_hash, 44, "miner3");
+
+        let mut blockids = BlockHashSet::default();
+        blockids.insert(42);
+        blockids.insert(43);
+        blockids.insert(44);
+        let (blockchain, remaining) = Blockchain::new_from_genesis_and_vec(
+            genesis,
+            vec![block1, block2, block3],
+        );
+
+        assert_eq!(blockids.len(), 3);
+
+        let root = root_children(&blockchain);
+        assert_eq!(root.len(), 2); // block1 and block2
+
+        // Find block1 in children
+        let block1_node = root
+            .iter()
+            .find(|n| n.miner == "miner1")
+            .unwrap();
+
+        assert_eq!(children(&blockchain, &block1_node.hash()).len(), 1); // block3
+        assert_eq!(children(&blockchain, &block1_node.hash())[0].miner, "miner3");
+        assert!(remaining.is_empty());
+    }
+
+    #[test]
+    fn test_orphaned_blocks() {
+        let genesis = create_test_block(&[], 0, "Genesis");
+        let fake_hash = vec![0xFF; 32]; 
+
+        let valid_block = create_test_block(&genesis.hash_block(), 42, "miner1");
+        
+        let orphan_block = create_test_block(&fake_hash, 43, "miner2");
+
+        let (blockchain, remaining) = Blockchain::new_from_genesis_and_vec(
+            genesis.clone(),
+            vec![valid_block.clone(), orphan_block.clone()],
+        );
+
+        // Verify the valid block was added to the blockchain
+        assert_eq!(root_children(&blockchain).len(), 1);
+        assert_eq!(root_children(&blockchain)[0].nonce, 42);
+        assert_eq!(root_children(&blockchain)[0].miner, "miner1");
+        
+        // Verify the orphan block is in the remaining list
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].nonce, 43);
+        assert_eq!(remaining[0].miner, "miner2");
+    }
+
+    #[test]
+    fn test_duplicate_valid_blocks() {
+        // println!("TEST: Starting test_duplicate_valid_blocks");
+        // Create a genesis block
+        let genesis = create_test_block(&[], 0, "Genesis");
+        let genesis_hash = genesis.hash_block().to_vec();
+        // println!("TEST: Genesis hash: {:?}", genesis_hash);
+
+        // Create first block off genesis
+        let block1 = create_test_block(&genesis_hash, 42, "miner1");
+        let block1_hash = block1.hash_block().to_vec();
+        // println!("TEST: Block1 hash: {:?}", block1_hash);
+
+        // Create another block off genesis
+        let block2 = create_test_block(&genesis_hash, 43, "miner2");
+        // println!("TEST: Block2 hash: {:?}", block2.hash_block().to_vec());
+        
+        // Create a block off block1
+        let block3 = create_test_block(&block1_hash, 44, "miner3");
+        // println!("TEST: Block3 hash: {:?}", block3.hash_block().to_vec());
+        // println!("TEST: Block3 parent hash: {:?}", block3.parent_hash);
+
+        // Build the blockchain including duplicates of the blocks
+        // println!("TEST: Building blockchain with blocks");
+        let (blockchain, remaining) = Blockchain::new_from_genesis_and_vec(
+            genesis.clone(),
+            vec![
+                block1.clone(), 
+                block2.clone(), 
+                block3.clone(),
+                // Include duplicate blocks with the same nonces
+                block1.clone(),
+                block2.clone()
+            ],
+        );
+
+        // Verify structure of the blockchain
+        let root = root_children(&blockchain);
+        // println!("TEST: Root children count: {}", root.children().len());
+        
+        // // Print the children of the root
+        // for (i, child) in root.children().iter().enumerate() {
+        //     println!("TEST: Root child {}: nonce={}, miner={}", i, child.value().nonce, child.value().miner);
+        //     for (j, grandchild) in child.children().iter().enumerate() {
+        //         println!("TEST: Grandchild {}.{}: nonce={}, miner={}", i, j, grandchild.value().nonce, grandchild.value().miner);
+        //     }
+        // }
+        
+        // Should have 2 children from genesis (block1 and block2)
+        assert_eq!(root.len(), 2);
+        
+        // Find block1 in the children
+        let block1_node = root
+            .iter()
+            .find(|n| n.nonce == 42)
+            .unwrap();
+        
+        // println!("TEST: Block1 node children count: {}", block1_node.children().len());
+        
+        // Verify block1 has block3 as a child
+        assert_eq!(children(&blockchain, &block1_node.hash()).len(), 1);
+        assert_eq!(children(&blockchain, &block1_node.hash())[0].nonce, 44);
+        assert_eq!(children(&blockchain, &block1_node.hash())[0].miner, "miner3");
+        
+        // Verify no blocks remain unprocessed
+        // println!("TEST: Remaining blocks count: {}", remaining.len());
+        // for (i, block) in remaining.iter().enumerate() {
+        //     println!("TEST: Remaining block {}: nonce={}, miner={}", i, block.nonce, block.miner);
+        // }
+        assert_eq!(remaining.len(), 0);
+    }
+
+    #[test]
+    fn test_complex_structure() {
+        let genesis = create_test_block(&[], 0, "Genesis");
+        let genesis_hash = genesis.hash_block().to_vec();
+
+        // Create blocks
+        let block1 = create_test_block(&genesis_hash, 42, "miner1");
+        let block1_hash = block1.hash_block().to_vec();
+
+        let block2 = create_test_block(&genesis_hash, 43, "miner2");
+        let block2_hash = block2.hash_block().to_vec();
+
+        let block3 = create_test_block(&block1_hash, 44, "miner3");
+        let block4 = create_test_block(&block2_hash, 45, "miner4");
+        let block5 = create_test_block(&block2_hash, 46, "miner5");
+
+        let (blockchain, _) = Blockchain::new_from_genesis_and_vec(
+            genesis,
+            vec![block1, block2, block3, block4, block5],
+        );
+
+        // Verify structure
+        let root = root_children(&blockchain);
+        assert_eq!(root.len(), 2);
+
+        let block1_node = root
+            .iter()
+            .find(|n| n.miner == "miner1")
+            .unwrap();
+        assert_eq!(children(&blockchain, &block1_node.hash()).len(), 1);
+        assert_eq!(children(&blockchain, &block1_node.hash())[0].miner, "miner3");
+
+        let block2_node = root
+            .iter()
+            .find(|n| n.miner == "miner2")
+            .unwrap();
+        assert_eq!(children(&blockchain, &block2_node.hash()).len(), 2);
+        assert!(children(&blockchain, &block2_node.hash())
+            .iter()
+            .any(|n| n.miner == "miner4"));
+        assert!(children(&blockchain, &block2_node.hash())
+            .iter()
+            .any(|n| n.miner == "miner5"));
+    }
+
+    #[test]
+    fn test_multiple_genesis() {
+        // Create a primary genesis block 
+        let genesis1 = create_test_block(&[], 0, "Genesis");
+        let genesis1_hash = genesis1.hash_block().to_vec();
+        
+        // Create a secondary genesis block with different nonce
+        let genesis2 = create_test_block(&[], 1, "Genesis");
+        let genesis2_hash = genesis2.hash_block().to_vec();
+    
+        // Create blocks that descend from genesis1
+        let block1 = create_test_block(&genesis1_hash, 42, "miner1");
+        let block1_hash = block1.hash_block().to_vec();
+        let block2 = create_test_block(&genesis1_hash, 43, "miner2");
+        let block3 = create_test_block(&block1_hash, 44, "miner3");
+    
+        // Create a block that descends from genesis2
+        let block4 = create_test_block(&genesis2_hash, 45, "miner4");
+    
+        // Build blockchain using genesis1, but include blocks from both genesis chains
+        let (blockchain, remaining) = Blockchain::new_from_genesis_and_vec(
+            genesis1.clone(),
+            vec![block1.clone(), block2.clone(), block3.clone(), block4.clone()],
+        );
+    
+        // Verify correct blocks were added to the tree
+        assert_eq!(root_children(&blockchain).len(), 2); // block1 and block2
+        
+        // Find block1 in the children and verify its child
+        let block1_node = *root_children(&blockchain).iter()
+            .find(|n| n.nonce == 42)
+            .unwrap();
+        assert_eq!(children(&blockchain, &block1_node.hash()).len(), 1);
+        assert_eq!(children(&blockchain, &block1_node.hash())[0].nonce, 44);
+        
+        // Verify blocks from the other genesis chain are in remaining
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].nonce, 45);
+        assert_eq!(remaining[0].miner, "miner4");
+    }
+
+    #[test]
+    fn test_store_before_connecting() {
+        let genesis = create_test_block(&[], 0, "Genesis");
+        let genesis_hash = genesis.hash_block().to_vec();
+        let block1 = create_test_block(&genesis_hash, 42, "miner1");
+        let block1_hash = block1.hash_block().to_vec();
+        let block2 = create_test_block(&block1_hash, 43, "miner2");
+        let block3 = create_test_block(&block1_hash, 44, "miner3");
+        let mut blockchain = Blockchain::new_from_genesis(genesis.clone());
+
+        #[derive(Debug, PartialEq)]
+        enum StoreError {
+            DiskFull,
+            Invalid(BlockValidationError),
+        }
+        impl From<BlockValidationError> for StoreError {
+            fn from(e: BlockValidationError) -> Self {
+                StoreError::Invalid(e)
+            }
+        }
+        let failing = |block: &Block| if block.nonce == 43 { Err(StoreError::DiskFull) } else { Ok(()) };
+
+        // A block that cannot be stored is not connected
+        assert_eq!(
+            blockchain.add_block_with(block1.clone(), |_| Err(StoreError::DiskFull)),
+            Err(StoreError::DiskFull)
+        );
+        assert!(!blockchain.contains(&block1_hash));
+        assert_eq!(blockchain.ledger().tip(), Some(genesis_hash.as_slice()));
+
+        // Nor is an orphan it connects
+        for orphan in [block2.clone(), block3.clone()] {
+            assert_eq!(blockchain.add_block(orphan), Ok(BlockStatus::Orphaned));
+        }
+        let mut stored = Vec::new();
+        let status = blockchain.add_block_with(block1, |block: &Block| -> Result<(), StoreError> {
+            failing(block)?;
+            stored.push(block.hash_block().to_vec());
+            Ok(())
+        });
+        assert_eq!(status, Ok(BlockStatus::Connected(stored.clone())));
+        assert_eq!(stored, vec![block1_hash, block3.hash_block().to_vec()]);
+        assert!(!blockchain.contains(&block2.hash_block()));
+        assert!(!blockchain.is_orphan(&block2.hash_block()));
+    }
+
+    #[test]
+    fn test_add_blocks_incrementally() {
+        let genesis = create_test_block(&[], 0, "Genesis");
+        let genesis_hash = genesis.hash_block().to_vec();
+
+        let block1 = create_test_block(&genesis_hash, 42, "miner1");
+        let block1_hash = block1.hash_block().to_vec();
+        let block2 = create_test_block(&block1_hash, 43, "miner2");
+        let block2_hash = block2.hash_block().to_vec();
+        let block3 = create_test_block(&block2_hash, 44, "miner3");
+
+        let mut blockchain = Blockchain::new_from_genesis(genesis);
+
+        // block3 arrives before its parent and waits in the orphan pool
+        let connected = blockchain.add_blocks(vec![block1.clone(), block3.clone()]);
+        assert_eq!(connected, vec![block1_hash.clone()]);
+        assert_eq!(blockchain.orphans().count(), 1);
+        assert!(blockchain.is_orphan(&block3.hash_block()));
+        assert!(!blockchain.is_orphan(&block1_hash));
+
+        // Known blocks are skipped, and the orphan is connected with its parent
+        let connected = blockchain.add_blocks(vec![block1, block2, block3.clone()]);
+        assert_eq!(connected, vec![block2_hash, block3.hash_block().to_vec()]);
+        assert_eq!(blockchain.orphans().count(), 0);
+        assert!(!blockchain.is_orphan(&block3.hash_block()));
+        assert_eq!(blockchain.blocks.len(), 4);
+
+        // Nothing new
+        assert!(blockchain.add_blocks(vec![block3]).is_empty());
+
+        // Blocks which are not properly signed are dropped
+        let mut forged = create_test_block(&block1_hash, 45, "miner4");
+        forged.miner = "someone else".to_string();
+        assert!(blockchain.add_blocks(vec![forged]).is_empty());
+        assert_eq!(blockchain.orphans().count(), 0);
+    }
+
+    #[test]
+    fn test_fork_choice_prefers_most_work() {
+        let genesis = create_test_block(&[], 0, "Genesis");
+        let genesis_hash = genesis.hash_block().to_vec();
+
+        // A long chain of blocks with no proof of work
+        let mut blocks = Vec::new();
+        let mut parent = genesis_hash.clone();
+        for nonce in 1..=4 {
+            let block = create_test_block(&parent, nonce, "long");
+            parent = block.hash_block().to_vec();
+            blocks.push(block);
+        }
+
+        // A single block with a lot more work than required
+        let mut heavy = create_test_block(&genesis_hash, 1, "heavy");
+        while leading_zeros(&heavy.hash_block()) < 16 {
+            heavy.nonce += 1;
+        }
+
+        let mut blockchain = Blockchain::new_from_genesis(genesis);
+        blockchain.add_blocks(blocks);
+        assert_eq!(blockchain.best_tip(), parent.as_slice());
+        assert_eq!(blockchain.best_chain().len(), 5);
+
+        blockchain.add_blocks(vec![heavy.clone()]);
+        assert_eq!(blockchain.best_tip(), heavy.hash_block().as_slice());
+        assert_eq!(blockchain.best_chain().len(), 2);
+        assert!(blockchain.chain_work(blockchain.best_tip()).unwrap() >= 1 << 16);
+    }
+
+    #[test]
+    fn test_fork_choice_tie_break() {
+        let genesis = create_test_block(&[], 0, "Genesis");
+        let genesis_hash = genesis.hash_block().to_vec();
+
+        // Two competing blocks proving the same amount of work
+        let mut candidates = (1..)
+            .map(|nonce| create_test_block(&genesis_hash, nonce, "miner"))
+            .filter(|block| hash_work(&block.hash_block()) == 1);
+        let block1 = candidates.next().unwrap();
+        let block2 = candidates.next().unwrap();
+        let expected = std::cmp::min(block1.hash_block(), block2.hash_block());
+
+        // The smallest hash wins whatever the insertion order
+        let (blockchain, _) =
+            Blockchain::new_from_genesis_and_vec(genesis.clone(), vec![block1.clone(), block2.clone()]);
+        assert_eq!(blockchain.best_tip(), expected.as_slice());
+        let (blockchain, _) = Blockchain::new_from_genesis_and_vec(genesis, vec![block2, block1]);
+        assert_eq!(blockchain.best_tip(), expected.as_slice());
+    }
+
+    #[test]
+    fn test_next_context() {
+        let mut genesis = create_test_block(&[], 0, "Genesis");
+        genesis.difficulty = 3;
+        let genesis_hash = genesis.hash_block().to_vec();
+        let mut block1 = create_test_block(&genesis_hash, 42, "miner1");
+        block1.difficulty = 3;
+        block1.sign(&test_key());
+        let mut rng = StdRng::seed_from_u64(42);
+        block1.solve_block(&mut rng, 3, None).unwrap();
+        let block1_hash = block1.hash_block().to_vec();
+
+        let (blockchain, _) = Blockchain::new_from_genesis_and_vec(genesis.clone(), vec![block1.clone()]);
+        let context = blockchain.next_context(&block1_hash).unwrap();
+        assert_eq!(context.difficulty, 3);
+        assert_eq!(context.median_time_past, block1.timestamp);
+        assert_eq!(blockchain.ancestors(&block1_hash).count(), 2);
+        assert!(blockchain.next_context(&[0xFF; 32]).is_none());
+    }
+
+    #[test]
+    fn test_invalid_blocks() {
+        let genesis = create_test_block(&[], 0, "Genesis");
+        let genesis_hash = genesis.hash_block().to_vec();
+        let mut blockchain = Blockchain::new_from_genesis(genesis.clone());
+
+        // Wrong difficulty
+        let mut block = create_test_block(&genesis_hash, 42, "miner1");
+        block.difficulty = 1;
+        block.sign(&test_key());
+        assert!(matches!(
+            blockchain.add_block(block),
+            Err(BlockValidationError::UnexpectedDifficulty { .. })
+        ));
+
+        // Not later than the median time past
+        let block = create_test_block(&genesis_hash, 0, "miner1");
+        assert!(blockchain.add_block(block).is_err());
+
+        // Another genesis
+        let other_genesis = create_test_block(&[], 1, "Genesis");
+        assert_eq!(blockchain.add_block(other_genesis), Err(BlockValidationError::InvalidGenesis));
+
+        // An orphan turning out to be invalid is dropped when its parent arrives
+        let block1 = create_test_block(&genesis_hash, 42, "miner1");
+        let block1_hash = block1.hash_block().to_vec();
+        let block2 = create_test_block(&block1_hash, 0, "miner2");
+        assert_eq!(blockchain.add_block(block2), Ok(BlockStatus::Orphaned));
+        assert_eq!(
+            blockchain.add_block(block1.clone()),
+            Ok(BlockStatus::Connected(vec![block1_hash]))
+        );
+        assert_eq!(blockchain.orphans().count(), 0);
+        assert_eq!(blockchain.add_block(block1), Ok(BlockStatus::Duplicate));
+    }
+
+    #[test]
+    fn test_ledger_follows_best_chain() {
+        let genesis = create_test_block(&[], 0, "Genesis");
+        let genesis_hash = genesis.hash_block().to_vec();
+        let miner = test_key().verifying_key().to_bytes().to_vec();
+        let other = SigningKey::from_bytes(&[8; 32]);
+        let reward = crate::ledger::block_reward(1);
+        let mut blockchain = Blockchain::new_from_genesis(genesis.clone());
+
+        // The miner spends the reward of its first block in its second one
+        let block1 = create_test_block(&genesis_hash, 1, "miner");
+        let tx = Transaction::transfer(&test_key(), vec![2; 32], 10, 1, 0, &genesis.network_id);
+        let mut block2 = create_test_block(&block1.hash_block(), 2, "miner");
+        block2.set_transactions(vec![tx.clone()]);
+        block2.sign(&test_key());
+        blockchain.add_blocks(vec![block1.clone(), block2.clone()]);
+        assert_eq!(blockchain.ledger().account(&miner).balance, 2 * reward - 10);
+        assert_eq!(blockchain.ledger().account(&[2; 32]).balance, 10);
+
+        // A heavier branch mined by someone else takes over
+        let mut heavy = create_test_block(&genesis_hash, 1, "heavy");
+        heavy.sign(&other);
+        while leading_zeros(&heavy.hash_block()) < 16 {
+            heavy.nonce += 1;
+        }
+        blockchain.add_block(heavy.clone()).unwrap();
+        assert_eq!(blockchain.best_tip(), heavy.hash_block().as_slice());
+        assert_eq!(blockchain.ledger().account(&miner), Account::default());
+        assert_eq!(blockchain.ledger().account(&[2; 32]), Account::default());
+        assert_eq!(blockchain.ledger().account(&other.verifying_key().to_bytes()).balance, reward);
+
+        // Blocks are checked against the ledger of their own branch
+        let mut block3 = create_test_block(&block2.hash_block(), 3, "miner");
+        let tx = Transaction::transfer(&test_key(), vec![2; 32], 10, 0, 1, &genesis.network_id);
+        block3.set_transactions(vec![tx.clone()]);
+        block3.sign(&test_key());
+        assert!(matches!(blockchain.add_block(block3), Ok(BlockStatus::Connected(_))));
+        let mut spend = create_test_block(&heavy.hash_block(), 2, "miner");
+        spend.set_transactions(vec![tx]);
+        spend.sign(&test_key());
+        assert_eq!(
+            blockchain.add_block(spend),
+            Err(BlockValidationError::InvalidTransaction {
+                index: 0,
+                error: TransactionError::BadNonce { nonce: 1, expected: 0 }
+            })
+        );
+        assert_eq!(blockchain.ledger().account(&[2; 32]), Account::default());
+    }
+
+    #[test]
+    fn test_orphan_pool_limits() {
+        let now = Instant::now();
+        let mut pool = OrphanPool::default();
+        for nonce in 0..MAX_ORPHANS as u64 + 10 {
+            let block = create_test_block(&[0xFF; 32], nonce, "miner");
+            pool.insert(block.hash(), block, now);
+        }
+
+        // The oldest orphans were evicted
+        assert_eq!(pool.iter().count(), MAX_ORPHANS);
+        assert!(!pool.contains(&create_test_block(&[0xFF; 32], 0, "miner").hash()));
+
+        // Then all of them expire
+        pool.prune(now + ORPHAN_EXPIRY);
+        assert_eq!(pool.iter().count(), 0);
+        assert!(pool.take_children(&[0xFF; 32]).is_empty());
+    }
+}
@@ -0,0 +1,131 @@
+use crate::block::Block;
+use crate::block::ChainContext;
+use crate::block::DanceMove;
+use crate::block::RetargetParams;
+use crate::consensus::ConsensusRules;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Default file holding the chain spec of a network.
+pub const DEFAULT_CHAIN_SPEC_FILE: &str = "chain.json";
+
+/// Everything the server and the miners of a network must agree on: its
+/// id, its genesis block and how the difficulty evolves. Stored as JSON,
+/// with the retarget parameters at the top level:
+///
+/// ```json
+/// {"network_id":"dancechain","block_interval":10,"retarget_interval":16,
+///  "max_retarget_step":2,"min_difficulty":25,"genesis":{...}}
+/// ```
+///
+/// The spec is the [`ConsensusRules`] of its network.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ChainSpec {
+    /// Id every block of the network commits to.
+    pub network_id: String,
+    #[serde(flatten)]
+    pub params: RetargetParams,
+    pub genesis: Block,
+}
+
+impl ChainSpec {
+    /// Creates the spec of a new network, mining its genesis block at
+    /// `difficulty`, which is also the minimum difficulty of the chain.
+    pub fn create(network_id: &str, difficulty: u32) -> Self {
+        let mut genesis = Block::new(Vec::new(), "Genesis".to_string(), 0, DanceMove::Y);
+        genesis.network_id = network_id.to_string();
+        genesis.solve_block(&mut rand::thread_rng(), difficulty, None);
+        ChainSpec {
+            network_id: network_id.to_string(),
+            params: RetargetParams {
+                min_difficulty: difficulty,
+                ..RetargetParams::default()
+            },
+            genesis,
+        }
+    }
+
+    /// Loads and checks the spec stored in `path`.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let spec: ChainSpec = serde_json::from_str(&fs::read_to_string(path)?)?;
+        spec.check()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(spec)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+
+    fn check(&self) -> Result<(), &'static str> {
+        if !self.is_genesis(&self.genesis, self.params.min_difficulty) {
+            return Err("not a valid genesis block for this network");
+        }
+        if self.params.block_interval == 0 || self.params.retarget_interval < 2 {
+            return Err("block and retarget intervals too short");
+        }
+        Ok(())
+    }
+}
+
+impl ConsensusRules for ChainSpec {
+    fn network_id(&self) -> &str {
+        &self.network_id
+    }
+
+    fn next_context(&self, parent_height: u64, ancestors: &mut dyn Iterator<Item = &Block>) -> ChainContext {
+        ChainContext::with_params(&self.params, parent_height, ancestors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::Blockchain;
+    use crate::consensus::DefaultRules;
+    use crate::error::BlockValidationError;
+    use ed25519_dalek::SigningKey;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("chain-spec-{}.json", std::process::id()));
+        let spec = ChainSpec::create("testnet", 4);
+        spec.save(&path).unwrap();
+        assert_eq!(ChainSpec::load(&path).unwrap(), spec);
+
+        // The genesis must belong to the network
+        let mut other = spec.clone();
+        other.network_id = "othernet".to_string();
+        other.save(&path).unwrap();
+        assert_eq!(ChainSpec::load(&path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_networks_do_not_mix() {
+        let spec = ChainSpec::create("testnet", 4);
+        assert!(!DefaultRules.is_genesis(&spec.genesis, 0));
+
+        let mut block = Block::new(spec.genesis.hash_block().to_vec(), "miner".to_string(), 0, DanceMove::M);
+        block.timestamp = spec.genesis.timestamp + 1;
+        block.difficulty = 4;
+        block.sign(&SigningKey::from_bytes(&[7; 32]));
+        block.solve_block(&mut rand::thread_rng(), 4, None);
+        assert_eq!(
+            spec.validate_header(&block, 0),
+            Err(BlockValidationError::WrongNetwork {
+                network_id: "dancechain".to_string(),
+                expected: "testnet".to_string()
+            })
+        );
+
+        block.network_id = "testnet".to_string();
+        block.sign(&SigningKey::from_bytes(&[7; 32]));
+        block.solve_block(&mut rand::thread_rng(), 4, None);
+        let mut blockchain = Blockchain::with_rules(spec.genesis.clone(), Arc::new(spec));
+        assert!(blockchain.add_block(block).is_ok());
+    }
+}
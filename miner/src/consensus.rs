@@ -0,0 +1,240 @@
+use crate::block::hash_work;
+use crate::block::leading_zeros;
+use crate::block::unix_time;
+use crate::block::Block;
+use crate::block::ChainContext;
+use crate::block::DEFAULT_NETWORK_ID;
+use crate::block::MAX_BLOCK_TRANSACTIONS;
+use crate::block::MAX_FUTURE_BLOCK_TIME;
+use crate::error::BlockValidationError;
+use crate::ledger;
+use std::fmt;
+
+/// The rules deciding which blocks are valid and which chain is the best,
+/// used by [`Blockchain`](crate::blockchain::Blockchain) and the nodes
+/// built on it.
+///
+/// Every method defaults to the rules of our network, so that an alternative
+/// rule set only overrides the rules it changes, such as the miner names it
+/// accepts or the proof of work it requires.
+pub trait ConsensusRules: fmt::Debug + Send + Sync {
+    /// Network whose blocks we accept, which every block commits to.
+    fn network_id(&self) -> &str {
+        DEFAULT_NETWORK_ID
+    }
+
+    /// Checks the name of the miner: "changemeyoufool" is banned and
+    /// "Genesis" is reserved to the genesis block.
+    fn check_miner_name(&self, block: &Block) -> Result<(), BlockValidationError> {
+        if block.miner == "changemeyoufool" || (block.miner == "Genesis" && !block.parent_hash.is_empty()) {
+            return Err(BlockValidationError::ForbiddenMinerName { name: block.miner.clone() });
+        }
+        Ok(())
+    }
+
+    /// Checks that a block hash meets `difficulty`, its number of leading
+    /// zero bits.
+    fn check_proof_of_work(&self, hash: &[u8], difficulty: u32) -> Result<(), BlockValidationError> {
+        let actual_bits = leading_zeros(hash);
+        if actual_bits < difficulty {
+            return Err(BlockValidationError::InvalidProofOfWork {
+                actual_bits,
+                required_bits: difficulty,
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks that do not depend on the ancestors of the block: the network,
+    /// the miner name, the dance move, the signature, the timestamp not being
+    /// too far in the future, the Merkle root of the transactions, and the
+    /// proof of work for the difficulty the block commits to, which must be
+    /// at least `min_difficulty`.
+    fn validate_header(&self, block: &Block, min_difficulty: u32) -> Result<(), BlockValidationError> {
+        if block.network_id != self.network_id() {
+            return Err(BlockValidationError::WrongNetwork {
+                network_id: block.network_id.clone(),
+                expected: self.network_id().to_string(),
+            });
+        }
+        self.check_miner_name(block)?;
+
+        let dance_value = block.dancemove as u8;
+        if !(1..=4).contains(&dance_value) {
+            return Err(BlockValidationError::InvalidDanceMove { value: dance_value });
+        }
+
+        // Only the genesis block is not signed
+        if !block.parent_hash.is_empty() {
+            block.verify_signature()?;
+        }
+
+        let max_timestamp = unix_time() + MAX_FUTURE_BLOCK_TIME;
+        if block.timestamp > max_timestamp {
+            return Err(BlockValidationError::TimestampTooFarInFuture {
+                timestamp: block.timestamp,
+                max_timestamp,
+            });
+        }
+
+        if block.difficulty < min_difficulty {
+            return Err(BlockValidationError::DifficultyTooLow {
+                difficulty: block.difficulty,
+                min_difficulty,
+            });
+        }
+
+        if block.transactions.len() > MAX_BLOCK_TRANSACTIONS {
+            return Err(BlockValidationError::TooManyTransactions {
+                count: block.transactions.len(),
+                max: MAX_BLOCK_TRANSACTIONS,
+            });
+        }
+        if block.merkle_root != block.compute_merkle_root() {
+            return Err(BlockValidationError::InvalidMerkleRoot);
+        }
+
+        self.check_proof_of_work(&block.hash_block(), block.difficulty)
+    }
+
+    /// Rules a block at height `parent_height + 1` must follow, given its
+    /// ancestors from its parent back towards the genesis. See
+    /// [`ChainContext::from_ancestors`].
+    fn next_context(&self, parent_height: u64, ancestors: &mut dyn Iterator<Item = &Block>) -> ChainContext {
+        ChainContext::from_ancestors(parent_height, ancestors)
+    }
+
+    /// Checks a block against the rules given by its ancestors: on top of
+    /// [`ConsensusRules::validate_header`], the block must commit to the
+    /// expected difficulty and be later than the median time past.
+    fn validate_contextual(&self, block: &Block, context: &ChainContext) -> Result<(), BlockValidationError> {
+        if block.difficulty != context.difficulty {
+            return Err(BlockValidationError::UnexpectedDifficulty {
+                difficulty: block.difficulty,
+                expected: context.difficulty,
+            });
+        }
+        if block.timestamp <= context.median_time_past {
+            return Err(BlockValidationError::TimestampTooEarly {
+                timestamp: block.timestamp,
+                median_time_past: context.median_time_past,
+            });
+        }
+        self.validate_header(block, context.difficulty)
+    }
+
+    /// Whether the block can start a chain: it belongs to our network, has
+    /// no parent, is mined by "Genesis" and meets the difficulty it commits
+    /// to, which must be at least `min_difficulty`.
+    fn is_genesis(&self, block: &Block, min_difficulty: u32) -> bool {
+        block.network_id == self.network_id()
+            && block.parent_hash.is_empty()
+            && block.miner == "Genesis"
+            && block.difficulty >= min_difficulty
+            && self.check_proof_of_work(&block.hash_block(), block.difficulty).is_ok()
+    }
+
+    /// Amount credited to the miner of the block at `height`, on top of the
+    /// fees of its transactions. See [`ledger::block_reward`].
+    fn block_reward(&self, height: u64) -> u64 {
+        ledger::block_reward(height)
+    }
+
+    /// Work proven by a block, accumulated along chains for the fork choice.
+    fn block_work(&self, hash: &[u8]) -> u128 {
+        hash_work(hash)
+    }
+
+    /// Fork choice rule: whether the tip `hash` with `work` accumulated from
+    /// the genesis replaces the current best tip. The best tip is the one
+    /// with the most accumulated work, ties being broken by the smallest
+    /// hash so that every miner agrees on the same tip whatever the order
+    /// they received blocks in.
+    fn is_better_tip(&self, hash: &[u8], work: u128, best_tip: &[u8], best_work: u128) -> bool {
+        work > best_work || (work == best_work && hash < best_tip)
+    }
+}
+
+/// The rules of our network.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultRules;
+
+impl ConsensusRules for DefaultRules {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::DanceMove;
+    use crate::blockchain::BlockStatus;
+    use crate::blockchain::Blockchain;
+    use ed25519_dalek::SigningKey;
+    use std::sync::Arc;
+
+    /// Accepts any miner name, and keeps the first tip seen on ties.
+    #[derive(Debug)]
+    struct LenientRules;
+
+    impl ConsensusRules for LenientRules {
+        fn check_miner_name(&self, _block: &Block) -> Result<(), BlockValidationError> {
+            Ok(())
+        }
+
+        fn is_better_tip(&self, _hash: &[u8], work: u128, _best_tip: &[u8], best_work: u128) -> bool {
+            work > best_work
+        }
+    }
+
+    fn genesis() -> Block {
+        let mut genesis = Block::new(Vec::new(), "Genesis".to_string(), 0, DanceMove::Y);
+        genesis.difficulty = 1;
+        genesis.timestamp = 1;
+        while !DefaultRules.is_genesis(&genesis, 1) {
+            genesis.nonce += 1;
+        }
+        genesis
+    }
+
+    /// A child of `parent` whose hash proves exactly one bit of work.
+    fn child(parent: &Block, miner: &str) -> Block {
+        let mut block = Block::new(parent.hash_block().to_vec(), miner.to_string(), 0, DanceMove::M);
+        block.difficulty = 1;
+        block.timestamp = parent.timestamp + 1;
+        block.sign(&SigningKey::from_bytes(&[7; 32]));
+        while leading_zeros(&block.hash_block()) != 1 {
+            block.nonce += 1;
+        }
+        block
+    }
+
+    #[test]
+    fn test_miner_name_policy() {
+        let genesis = genesis();
+        let block = child(&genesis, "changemeyoufool");
+
+        let mut blockchain = Blockchain::new_from_genesis(genesis.clone());
+        assert!(matches!(
+            blockchain.add_block(block.clone()),
+            Err(BlockValidationError::ForbiddenMinerName { .. })
+        ));
+
+        let mut blockchain = Blockchain::with_rules(genesis, Arc::new(LenientRules));
+        assert!(matches!(blockchain.add_block(block), Ok(BlockStatus::Connected(_))));
+    }
+
+    #[test]
+    fn test_fork_choice() {
+        let genesis = genesis();
+        let a = child(&genesis, "a");
+        let b = child(&genesis, "b");
+        let (first, second) = if a.hash_block() < b.hash_block() { (b, a) } else { (a, b) };
+
+        // Ties go to the smallest hash by default, to the first tip otherwise
+        let mut blockchain = Blockchain::new_from_genesis(genesis.clone());
+        blockchain.add_blocks(vec![first.clone(), second.clone()]);
+        assert_eq!(blockchain.best_tip(), second.hash_block());
+
+        let mut blockchain = Blockchain::with_rules(genesis, Arc::new(LenientRules));
+        blockchain.add_blocks(vec![first.clone(), second]);
+        assert_eq!(blockchain.best_tip(), first.hash_block());
+    }
+}
@@ -0,0 +1,202 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Why a block was refused.
+///
+/// Servers send it to miners as JSON, tagged with a `code` which stays the
+/// same across versions, such as
+/// `{"code":"invalid_proof_of_work","actual_bits":3,"required_bits":8}`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum BlockValidationError {
+    /// The block belongs to another network.
+    WrongNetwork { network_id: String, expected: String },
+    /// The miner name is banned, or reserved to the genesis block.
+    ForbiddenMinerName { name: String },
+    InvalidDanceMove { value: u8 },
+    InvalidPublicKey,
+    InvalidSignature,
+    /// The block is dated later than we accept yet.
+    TimestampTooFarInFuture { timestamp: u64, max_timestamp: u64 },
+    /// The block is not later than the median time past of its ancestors.
+    TimestampTooEarly { timestamp: u64, median_time_past: u64 },
+    /// The block commits to a difficulty below the one the receiver accepts.
+    DifficultyTooLow { difficulty: u32, min_difficulty: u32 },
+    /// The block commits to another difficulty than the one its ancestors
+    /// require.
+    UnexpectedDifficulty { difficulty: u32, expected: u32 },
+    /// The hash of the block has fewer leading zero bits than required.
+    InvalidProofOfWork { actual_bits: u32, required_bits: u32 },
+    /// The Merkle root of the header does not match the transactions.
+    InvalidMerkleRoot,
+    TooManyTransactions { count: usize, max: usize },
+    /// The block has no parent but is not the genesis block of the chain.
+    InvalidGenesis,
+    /// The parent of the block is not in the chain.
+    UnknownParent,
+    /// A different block with the same hash is already known.
+    HashCollision,
+    /// A transaction of the block cannot be applied to the ledger of its
+    /// parent.
+    InvalidTransaction { index: usize, error: TransactionError },
+    /// Crediting the miner with the reward and the fees would overflow its
+    /// balance.
+    RewardOverflow,
+}
+
+impl BlockValidationError {
+    /// The stable code identifying the error in JSON.
+    pub fn code(&self) -> &'static str {
+        match self {
+            BlockValidationError::WrongNetwork { .. } => "wrong_network",
+            BlockValidationError::ForbiddenMinerName { .. } => "forbidden_miner_name",
+            BlockValidationError::InvalidDanceMove { .. } => "invalid_dance_move",
+            BlockValidationError::InvalidPublicKey => "invalid_public_key",
+            BlockValidationError::InvalidSignature => "invalid_signature",
+            BlockValidationError::TimestampTooFarInFuture { .. } => "timestamp_too_far_in_future",
+            BlockValidationError::TimestampTooEarly { .. } => "timestamp_too_early",
+            BlockValidationError::DifficultyTooLow { .. } => "difficulty_too_low",
+            BlockValidationError::UnexpectedDifficulty { .. } => "unexpected_difficulty",
+            BlockValidationError::InvalidProofOfWork { .. } => "invalid_proof_of_work",
+            BlockValidationError::InvalidMerkleRoot => "invalid_merkle_root",
+            BlockValidationError::TooManyTransactions { .. } => "too_many_transactions",
+            BlockValidationError::InvalidGenesis => "invalid_genesis",
+            BlockValidationError::UnknownParent => "unknown_parent",
+            BlockValidationError::HashCollision => "hash_collision",
+            BlockValidationError::InvalidTransaction { .. } => "invalid_transaction",
+            BlockValidationError::RewardOverflow => "reward_overflow",
+        }
+    }
+}
+
+impl fmt::Display for BlockValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BlockValidationError::WrongNetwork { network_id, expected } => {
+                write!(f, "Wrong network: {}, expected {}", network_id, expected)
+            }
+            BlockValidationError::ForbiddenMinerName { name } => write!(f, "Invalid miner name: {}", name),
+            BlockValidationError::InvalidDanceMove { value } => write!(f, "Invalid dance move: {}", value),
+            BlockValidationError::InvalidPublicKey => write!(f, "Invalid public key"),
+            BlockValidationError::InvalidSignature => write!(f, "Invalid signature"),
+            BlockValidationError::TimestampTooFarInFuture { timestamp, max_timestamp } => write!(
+                f,
+                "Timestamp too far in the future: {}, at most {}",
+                timestamp, max_timestamp
+            ),
+            BlockValidationError::TimestampTooEarly { timestamp, median_time_past } => write!(
+                f,
+                "Timestamp not later than the median time past: {}, median {}",
+                timestamp, median_time_past
+            ),
+            BlockValidationError::DifficultyTooLow { difficulty, min_difficulty } => {
+                write!(f, "Difficulty too low: {}, at least {}", difficulty, min_difficulty)
+            }
+            BlockValidationError::UnexpectedDifficulty { difficulty, expected } => {
+                write!(f, "Unexpected difficulty: {}, expected {}", difficulty, expected)
+            }
+            BlockValidationError::InvalidProofOfWork { actual_bits, required_bits } => write!(
+                f,
+                "Invalid proof of work: {} leading zero bits, {} required",
+                actual_bits, required_bits
+            ),
+            BlockValidationError::InvalidMerkleRoot => write!(f, "Invalid Merkle root"),
+            BlockValidationError::TooManyTransactions { count, max } => {
+                write!(f, "Too many transactions: {}, at most {}", count, max)
+            }
+            BlockValidationError::InvalidGenesis => write!(f, "Unexpected genesis block"),
+            BlockValidationError::UnknownParent => write!(f, "Unknown parent"),
+            BlockValidationError::HashCollision => write!(f, "Hash collision"),
+            BlockValidationError::InvalidTransaction { index, error } => {
+                write!(f, "Invalid transaction {}: {}", index, error)
+            }
+            BlockValidationError::RewardOverflow => write!(f, "Reward overflows the balance of the miner"),
+        }
+    }
+}
+
+impl std::error::Error for BlockValidationError {}
+
+/// Why a transaction cannot be applied to the ledger.
+///
+/// Sent as JSON tagged with a `code`, like [`BlockValidationError`].
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum TransactionError {
+    InvalidPublicKey,
+    InvalidSignature,
+    /// The nonce is not the number of transactions the sender already sent.
+    BadNonce { nonce: u64, expected: u64 },
+    /// The sender cannot pay the amount and the fee.
+    InsufficientBalance { balance: u64, needed: u64 },
+    /// A balance would not fit in a u64.
+    Overflow,
+}
+
+impl TransactionError {
+    /// The stable code identifying the error in JSON.
+    pub fn code(&self) -> &'static str {
+        match self {
+            TransactionError::InvalidPublicKey => "invalid_public_key",
+            TransactionError::InvalidSignature => "invalid_signature",
+            TransactionError::BadNonce { .. } => "bad_nonce",
+            TransactionError::InsufficientBalance { .. } => "insufficient_balance",
+            TransactionError::Overflow => "overflow",
+        }
+    }
+}
+
+impl fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransactionError::InvalidPublicKey => write!(f, "Invalid public key"),
+            TransactionError::InvalidSignature => write!(f, "Invalid signature"),
+            TransactionError::BadNonce { nonce, expected } => write!(f, "Bad nonce: {}, expected {}", nonce, expected),
+            TransactionError::InsufficientBalance { balance, needed } => {
+                write!(f, "Insufficient balance: {}, {} needed", balance, needed)
+            }
+            TransactionError::Overflow => write!(f, "Balance overflow"),
+        }
+    }
+}
+
+impl std::error::Error for TransactionError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json() {
+        let error = BlockValidationError::InvalidProofOfWork {
+            actual_bits: 3,
+            required_bits: 8,
+        };
+        let json = serde_json::to_string(&error).unwrap();
+        assert_eq!(json, r#"{"code":"invalid_proof_of_work","actual_bits":3,"required_bits":8}"#);
+        assert_eq!(serde_json::from_str::<BlockValidationError>(&json).unwrap(), error);
+
+        // The codes of the JSON form are the ones of `code`
+        for error in [
+            BlockValidationError::ForbiddenMinerName { name: "Genesis".to_string() },
+            BlockValidationError::TimestampTooEarly {
+                timestamp: 1,
+                median_time_past: 2,
+            },
+            BlockValidationError::HashCollision,
+            BlockValidationError::RewardOverflow,
+            BlockValidationError::InvalidTransaction {
+                index: 0,
+                error: TransactionError::BadNonce { nonce: 2, expected: 1 },
+            },
+        ] {
+            let json = serde_json::to_value(&error).unwrap();
+            assert_eq!(json["code"], error.code());
+            assert_eq!(serde_json::from_value::<BlockValidationError>(json).unwrap(), error);
+        }
+
+        let error = TransactionError::InsufficientBalance { balance: 1, needed: 2 };
+        let json = serde_json::to_value(&error).unwrap();
+        assert_eq!(json["code"], error.code());
+    }
+}
@@ -0,0 +1,59 @@
+use ed25519_dalek::SigningKey;
+use rand::rngs::OsRng;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Default location of the miner's secret key.
+pub const DEFAULT_KEY_FILE: &str = "miner.key";
+
+/// Generates a new random keypair
+pub fn generate() -> SigningKey {
+    SigningKey::generate(&mut OsRng)
+}
+
+/// Stores the secret key, hex encoded, in `path`.
+/// Fails if the file already exists, so that we never lose a key.
+pub fn save(key: &SigningKey, path: &Path) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    io::Write::write_all(&mut file, hex::encode(key.to_bytes()).as_bytes())
+}
+
+/// Loads a secret key stored by [`save`]
+pub fn load(path: &Path) -> io::Result<SigningKey> {
+    let content = fs::read_to_string(path)?;
+    let bytes = hex::decode(content.trim())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let secret: [u8; 32] = bytes
+        .try_into()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "a key is 32 bytes long"))?;
+    Ok(SigningKey::from_bytes(&secret))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("miner-key-test-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let key = generate();
+        save(&key, &path).unwrap();
+        assert_eq!(load(&path).unwrap().to_bytes(), key.to_bytes());
+
+        // Existing keys are never overwritten
+        assert!(save(&generate(), &path).is_err());
+        assert_eq!(load(&path).unwrap().to_bytes(), key.to_bytes());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
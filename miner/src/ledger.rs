@@ -0,0 +1,316 @@
+use crate::block::Block;
+use crate::error::BlockValidationError;
+use crate::error::TransactionError;
+use crate::transaction::Transaction;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Reward of the miner of each block until the first halving, in the
+/// smallest unit of the currency.
+pub const INITIAL_REWARD: u64 = 5_000_000_000;
+/// Number of blocks after which the reward halves.
+pub const HALVING_INTERVAL: u64 = 100_000;
+
+/// Reward of the miner of the block at `height`: [`INITIAL_REWARD`], halved
+/// every [`HALVING_INTERVAL`] blocks. The genesis block has no miner to
+/// reward.
+pub fn block_reward(height: u64) -> u64 {
+    if height == 0 {
+        return 0;
+    }
+    u32::try_from(height / HALVING_INTERVAL)
+        .ok()
+        .and_then(|halvings| INITIAL_REWARD.checked_shr(halvings))
+        .unwrap_or(0)
+}
+
+/// State of an account, identified by its public key.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Account {
+    pub balance: u64,
+    /// Number of transactions the account sent, which is the nonce of its
+    /// next transaction.
+    pub nonce: u64,
+}
+
+impl Account {
+    /// Checks that the account can send the transaction next: its nonce is
+    /// the next one and the account can pay for it. The signature is not
+    /// checked.
+    pub fn check_transaction(&self, tx: &Transaction) -> Result<(), TransactionError> {
+        if tx.nonce != self.nonce {
+            return Err(TransactionError::BadNonce {
+                nonce: tx.nonce,
+                expected: self.nonce,
+            });
+        }
+        let needed = tx.amount.checked_add(tx.fee).ok_or(TransactionError::Overflow)?;
+        if self.balance < needed {
+            return Err(TransactionError::InsufficientBalance {
+                balance: self.balance,
+                needed,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// A block applied to the ledger, with the accounts it changed as they were
+/// before, in the order they changed.
+#[derive(Debug)]
+struct AppliedBlock {
+    hash: Vec<u8>,
+    previous: Vec<(Vec<u8>, Option<Account>)>,
+}
+
+/// Accounts after applying a chain of blocks, from the genesis.
+///
+/// Blocks are applied one at a time on top of the last one, and reverted in
+/// the opposite order, so that the ledger can follow the best chain across
+/// reorganizations without being recomputed from the genesis.
+#[derive(Debug, Default)]
+pub struct Ledger {
+    accounts: HashMap<Vec<u8>, Account>,
+    /// Blocks applied, the block at height `h` being at index `h`.
+    applied: Vec<AppliedBlock>,
+}
+
+impl Ledger {
+    /// State of the account of the given public key
+    pub fn account(&self, key: &[u8]) -> Account {
+        self.accounts.get(key).copied().unwrap_or_default()
+    }
+
+    /// Accounts that received or sent anything
+    pub fn accounts(&self) -> impl Iterator<Item = (&[u8], &Account)> {
+        self.accounts.iter().map(|(key, account)| (key.as_slice(), account))
+    }
+
+    /// Hash of the block applied at the given height
+    pub fn hash_at(&self, height: u64) -> Option<&[u8]> {
+        let applied = self.applied.get(usize::try_from(height).ok()?)?;
+        Some(&applied.hash)
+    }
+
+    /// Hash of the last block applied
+    pub fn tip(&self) -> Option<&[u8]> {
+        self.applied.last().map(|applied| applied.hash.as_slice())
+    }
+
+    /// Height of the last block applied
+    pub fn height(&self) -> Option<u64> {
+        (self.applied.len() as u64).checked_sub(1)
+    }
+
+    /// Checks that a transaction of the network `network_id` can be applied
+    /// now: it is signed by its sender, who can pay for it, and its nonce is
+    /// the next one of the sender.
+    pub fn check_transaction(&self, tx: &Transaction, network_id: &str) -> Result<(), TransactionError> {
+        tx.verify_signature(network_id)?;
+        self.account(&tx.from).check_transaction(tx)
+    }
+
+    /// Applies the transactions of a block on top of the last block applied,
+    /// then credits its miner with `reward` and the fees. Nothing changes if
+    /// a transaction cannot be applied.
+    pub fn apply_block(&mut self, hash: Vec<u8>, block: &Block, reward: u64) -> Result<(), BlockValidationError> {
+        let mut previous = Vec::new();
+        match self.apply_transactions(block, reward, &mut previous) {
+            Ok(()) => {
+                self.applied.push(AppliedBlock { hash, previous });
+                Ok(())
+            }
+            Err(e) => {
+                self.restore(previous);
+                Err(e)
+            }
+        }
+    }
+
+    fn apply_transactions(
+        &mut self,
+        block: &Block,
+        reward: u64,
+        previous: &mut Vec<(Vec<u8>, Option<Account>)>,
+    ) -> Result<(), BlockValidationError> {
+        let mut fees: u64 = 0;
+        for (index, tx) in block.transactions.iter().enumerate() {
+            let invalid = |error| BlockValidationError::InvalidTransaction { index, error };
+            self.check_transaction(tx, &block.network_id).map_err(invalid)?;
+            fees = fees.checked_add(tx.fee).ok_or(invalid(TransactionError::Overflow))?;
+            self.update(&tx.from, previous, |sender| {
+                sender.balance -= tx.amount + tx.fee;
+                sender.nonce += 1;
+                Some(())
+            })
+            .ok_or(invalid(TransactionError::Overflow))?;
+            self.update(&tx.to, previous, |recipient| {
+                recipient.balance = recipient.balance.checked_add(tx.amount)?;
+                Some(())
+            })
+            .ok_or(invalid(TransactionError::Overflow))?;
+        }
+        // Only the genesis block has no miner
+        if !block.public_key.is_empty() {
+            self.update(&block.public_key, previous, |miner| {
+                miner.balance = miner.balance.checked_add(reward)?.checked_add(fees)?;
+                Some(())
+            })
+            .ok_or(BlockValidationError::RewardOverflow)?;
+        }
+        Ok(())
+    }
+
+    /// Applies `change` to the account of `key`, recording its previous
+    /// state. The account is left unchanged if `change` returns None.
+    fn update(
+        &mut self,
+        key: &[u8],
+        previous: &mut Vec<(Vec<u8>, Option<Account>)>,
+        change: impl FnOnce(&mut Account) -> Option<()>,
+    ) -> Option<()> {
+        previous.push((key.to_vec(), self.accounts.get(key).copied()));
+        let mut account = self.account(key);
+        change(&mut account)?;
+        self.accounts.insert(key.to_vec(), account);
+        Some(())
+    }
+
+    /// Reverts the last block applied, returning its hash
+    pub fn revert_block(&mut self) -> Option<Vec<u8>> {
+        let applied = self.applied.pop()?;
+        self.restore(applied.previous);
+        Some(applied.hash)
+    }
+
+    fn restore(&mut self, previous: Vec<(Vec<u8>, Option<Account>)>) {
+        for (key, account) in previous.into_iter().rev() {
+            match account {
+                Some(account) => self.accounts.insert(key, account),
+                None => self.accounts.remove(&key),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::DanceMove;
+    use ed25519_dalek::SigningKey;
+
+    fn block(miner: &SigningKey, transactions: Vec<Transaction>) -> Block {
+        let mut block = Block::new(vec![1; 32], "miner".to_string(), 0, DanceMove::Y);
+        block.set_transactions(transactions);
+        block.sign(miner);
+        block
+    }
+
+    fn public_key(key: &SigningKey) -> Vec<u8> {
+        key.verifying_key().to_bytes().to_vec()
+    }
+
+    #[test]
+    fn test_block_reward() {
+        assert_eq!(block_reward(0), 0);
+        assert_eq!(block_reward(1), INITIAL_REWARD);
+        assert_eq!(block_reward(HALVING_INTERVAL - 1), INITIAL_REWARD);
+        assert_eq!(block_reward(HALVING_INTERVAL), INITIAL_REWARD / 2);
+        assert_eq!(block_reward(3 * HALVING_INTERVAL), INITIAL_REWARD / 8);
+        assert_eq!(block_reward(64 * HALVING_INTERVAL), 0);
+        assert_eq!(block_reward(u64::MAX), 0);
+    }
+
+    #[test]
+    fn test_apply_and_revert() {
+        let (alice, bob) = (SigningKey::from_bytes(&[1; 32]), SigningKey::from_bytes(&[2; 32]));
+        let network_id = Block::new(Vec::new(), String::new(), 0, DanceMove::Y).network_id;
+        let mut ledger = Ledger::default();
+
+        ledger.apply_block(vec![1], &block(&alice, vec![]), 100).unwrap();
+        assert_eq!(ledger.account(&public_key(&alice)), Account { balance: 100, nonce: 0 });
+
+        // Bob mines the transfer from Alice, earning its fee
+        let tx = Transaction::transfer(&alice, public_key(&bob), 30, 5, 0, &network_id);
+        ledger.apply_block(vec![2], &block(&bob, vec![tx.clone()]), 100).unwrap();
+        assert_eq!(ledger.account(&public_key(&alice)), Account { balance: 65, nonce: 1 });
+        assert_eq!(ledger.account(&public_key(&bob)), Account { balance: 135, nonce: 0 });
+        assert_eq!((ledger.tip(), ledger.height()), (Some(&[2][..]), Some(1)));
+
+        // The transfer cannot be replayed, nor overspend
+        assert_eq!(
+            ledger.check_transaction(&tx, &network_id),
+            Err(TransactionError::BadNonce { nonce: 0, expected: 1 })
+        );
+        let overspend = Transaction::transfer(&alice, public_key(&bob), 60, 6, 1, &network_id);
+        assert_eq!(
+            ledger.apply_block(vec![3], &block(&bob, vec![overspend]), 100),
+            Err(BlockValidationError::InvalidTransaction {
+                index: 0,
+                error: TransactionError::InsufficientBalance { balance: 65, needed: 66 }
+            })
+        );
+        assert_eq!(ledger.height(), Some(1));
+        assert_eq!(ledger.account(&public_key(&bob)).balance, 135);
+
+        assert_eq!(ledger.revert_block(), Some(vec![2]));
+        assert_eq!(ledger.account(&public_key(&alice)), Account { balance: 100, nonce: 0 });
+        assert_eq!(ledger.accounts().count(), 1);
+        assert_eq!(ledger.revert_block(), Some(vec![1]));
+        assert_eq!(ledger.accounts().count(), 0);
+        assert_eq!(ledger.revert_block(), None);
+    }
+
+    #[test]
+    fn test_block_is_applied_atomically() {
+        let (alice, bob) = (SigningKey::from_bytes(&[1; 32]), SigningKey::from_bytes(&[2; 32]));
+        let network_id = Block::new(Vec::new(), String::new(), 0, DanceMove::Y).network_id;
+        let mut ledger = Ledger::default();
+        ledger.apply_block(vec![1], &block(&alice, vec![]), 100).unwrap();
+
+        // Transactions of a block apply in order, the second one failing
+        let first = Transaction::transfer(&alice, public_key(&bob), 10, 0, 0, &network_id);
+        let second = Transaction::transfer(&alice, public_key(&bob), 10, 0, 0, &network_id);
+        assert!(matches!(
+            ledger.apply_block(vec![2], &block(&bob, vec![first.clone(), second]), 100),
+            Err(BlockValidationError::InvalidTransaction { index: 1, .. })
+        ));
+        assert_eq!(ledger.accounts().count(), 1);
+        assert_eq!(ledger.account(&public_key(&alice)), Account { balance: 100, nonce: 0 });
+
+        let second = Transaction::transfer(&alice, public_key(&bob), 10, 0, 1, &network_id);
+        ledger.apply_block(vec![2], &block(&bob, vec![first, second]), 100).unwrap();
+        assert_eq!(ledger.account(&public_key(&alice)), Account { balance: 80, nonce: 2 });
+        assert_eq!(ledger.account(&public_key(&bob)).balance, 120);
+    }
+
+    #[test]
+    fn test_credit_overflow() {
+        let (alice, bob) = (SigningKey::from_bytes(&[1; 32]), SigningKey::from_bytes(&[2; 32]));
+        let network_id = Block::new(Vec::new(), String::new(), 0, DanceMove::Y).network_id;
+        let mut ledger = Ledger::default();
+        ledger.apply_block(vec![1], &block(&alice, vec![]), 100).unwrap();
+        ledger.apply_block(vec![2], &block(&bob, vec![]), u64::MAX - 5).unwrap();
+
+        // Funds are refused rather than lost when a balance would overflow
+        let tx = Transaction::transfer(&alice, public_key(&bob), 10, 0, 0, &network_id);
+        assert_eq!(
+            ledger.apply_block(vec![3], &block(&alice, vec![tx.clone()]), 0),
+            Err(BlockValidationError::InvalidTransaction {
+                index: 0,
+                error: TransactionError::Overflow
+            })
+        );
+        assert_eq!(
+            ledger.apply_block(vec![3], &block(&bob, vec![]), 10),
+            Err(BlockValidationError::RewardOverflow)
+        );
+        assert_eq!(ledger.height(), Some(1));
+        assert_eq!(ledger.account(&public_key(&alice)), Account { balance: 100, nonce: 0 });
+        assert_eq!(ledger.account(&public_key(&bob)).balance, u64::MAX - 5);
+
+        let tx = Transaction::transfer(&alice, public_key(&bob), 5, 0, 0, &network_id);
+        ledger.apply_block(vec![3], &block(&alice, vec![tx]), 0).unwrap();
+        assert_eq!(ledger.account(&public_key(&bob)).balance, u64::MAX);
+    }
+}
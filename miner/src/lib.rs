@@ -1,2 +1,15 @@
 pub mod block;
+pub mod blockchain;
+pub mod chainspec;
+pub mod consensus;
+pub mod error;
+pub mod keys;
+pub mod ledger;
+pub mod merkle;
+pub mod mining;
+pub mod network;
+pub mod node;
+pub mod p2p;
+pub mod pool;
 pub mod simpletree;
+pub mod transaction;
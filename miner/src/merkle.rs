@@ -0,0 +1,62 @@
+use sha2::{Digest, Sha256};
+
+/// Root of an empty tree.
+pub const EMPTY_ROOT: [u8; 32] = [0; 32];
+
+/// Computes the root of the Merkle tree whose leaves are the given hashes.
+///
+/// Leaves and inner nodes are hashed with different prefixes, so that an
+/// inner node cannot pass for a leaf. A node without a sibling moves up a
+/// level unchanged instead of being paired with itself, so that two lists of
+/// leaves never share a root.
+pub fn merkle_root(leaves: &[[u8; 32]]) -> [u8; 32] {
+    if leaves.is_empty() {
+        return EMPTY_ROOT;
+    }
+    let mut level: Vec<[u8; 32]> = leaves
+        .iter()
+        .map(|leaf| Sha256::new().chain_update([0]).chain_update(leaf).finalize().into())
+        .collect();
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => Sha256::new()
+                    .chain_update([1])
+                    .chain_update(left)
+                    .chain_update(right)
+                    .finalize()
+                    .into(),
+                [single] => *single,
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+    level[0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaf(hash: &[u8; 32]) -> [u8; 32] {
+        Sha256::new().chain_update([0]).chain_update(hash).finalize().into()
+    }
+
+    fn node(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+        Sha256::new().chain_update([1]).chain_update(left).chain_update(right).finalize().into()
+    }
+
+    #[test]
+    fn test_merkle_root() {
+        let (a, b, c) = ([1; 32], [2; 32], [3; 32]);
+        assert_eq!(merkle_root(&[]), EMPTY_ROOT);
+        assert_eq!(merkle_root(&[a]), leaf(&a));
+        assert_eq!(merkle_root(&[a, b]), node(&leaf(&a), &leaf(&b)));
+        assert_eq!(merkle_root(&[a, b, c]), node(&node(&leaf(&a), &leaf(&b)), &leaf(&c)));
+
+        // Order matters, and the last leaf is not duplicated
+        assert_ne!(merkle_root(&[b, a]), merkle_root(&[a, b]));
+        assert_ne!(merkle_root(&[a, b, c, c]), merkle_root(&[a, b, c]));
+    }
+}
@@ -0,0 +1,260 @@
+use crate::block::Block;
+use crate::block::CancelHandle;
+use crate::block::DanceMove;
+use crate::blockchain::Blockchain;
+use crate::consensus::ConsensusRules;
+use crate::network::BlockTemplate;
+use crate::network::NetworkConnector;
+use crate::network::Transport;
+use crate::network::TransportError;
+use ed25519_dalek::SigningKey;
+use rand::thread_rng;
+use rand::RngCore;
+use std::sync::mpsc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use std::time::Instant;
+
+/// How often a miner working from templates asks for a new one, to learn
+/// about new best tips.
+pub const TEMPLATE_REFRESH: Duration = Duration::from_secs(1);
+
+/// Settings of a miner.
+#[derive(Clone, Debug)]
+pub struct MinerConfig {
+    /// Display name put in our blocks.
+    pub name: String,
+    /// Key signing our blocks.
+    pub key: SigningKey,
+    /// Number of threads searching for nonces.
+    pub threads: usize,
+    /// Genesis block of the chain we mine on, which must be the one of the
+    /// server.
+    pub genesis: Block,
+    /// Number of blocks to mine before stopping, forever if None.
+    pub max_blocks: Option<u64>,
+    /// Rules of the chain we mine on, which must be the ones of the server.
+    pub rules: Arc<dyn ConsensusRules>,
+}
+
+/// Mines on top of the best chain of the server reached through `transport`,
+/// until `max_blocks` blocks are mined or the connection to the server is
+/// lost. Returns our view of the chain.
+pub fn mine<T: Transport + Send + 'static>(config: &MinerConfig, transport: T) -> Blockchain {
+    // use message passing to communicate between the thread querying the server
+    // and sending any new block as a vector of blocks
+    let (tx1, rx1) = mpsc::sync_channel(1);
+    // use message passing to communicate between the thread(s) mining blocks
+    // and the thread interacting with the server.
+    let (tx2, rx2) = mpsc::channel();
+
+    let network = thread::spawn(move || {
+        let mut net = NetworkConnector::new(tx1, rx2, transport);
+        net.sync();
+    });
+
+    // Main mining loop
+    let mut bc = Blockchain::with_rules(config.genesis.clone(), config.rules.clone());
+    let mut job: Option<MiningJob> = None;
+    let mut mined = 0;
+    let mut rng = thread_rng();
+
+    while config.max_blocks.is_none_or(|max| mined < max) {
+        // Wait briefly for blocks from the network
+        match rx1.recv_timeout(Duration::from_millis(10)) {
+            Ok(new_blocks) => {
+                println!("Received {} blocks from network", new_blocks.len());
+
+                // A server on another chain sends its own genesis block
+                if new_blocks.iter().any(|block| block.parent_hash.is_empty() && *block != config.genesis) {
+                    println!("The server is on another chain than ours!");
+                }
+
+                let connected = bc.add_blocks(new_blocks);
+                if !connected.is_empty() {
+                    println!("Connected {} new blocks to the chain", connected.len());
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                // No new blocks, continue
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                println!("Network connection lost!");
+                break;
+            }
+        }
+
+        // Stop working on a tip which is not the best one anymore
+        if let Some(ref current) = job {
+            if current.parent_hash != bc.best_tip() && !current.cancel.is_cancelled() {
+                println!("New best tip, restarting mining");
+                current.cancel.cancel();
+            }
+        }
+
+        if job.as_ref().is_some_and(|job| job.handle.is_finished()) {
+            let finished = job.take().unwrap();
+            if let Some(new_block) = finished.handle.join().expect("Mining thread panicked") {
+                // The best tip may have changed while the solution was found
+                if new_block.parent_hash == bc.best_tip() {
+                    println!("Mined new block with dance move: {:?}, hash: {:?}", new_block.dancemove, new_block.hash_block());
+                    match bc.add_block(new_block.clone()) {
+                        // Send the new block to the network
+                        Ok(_) => {
+                            // The network thread stopped, so do we
+                            if tx2.send(new_block).is_err() {
+                                println!("Network connection lost!");
+                                break;
+                            }
+                            mined += 1;
+                        }
+                        Err(e) => println!("Mined an invalid block: {}", e),
+                    }
+                } else {
+                    println!("Dropping block mined on a stale tip");
+                }
+            }
+        }
+
+        // Mine on top of the tip with the most accumulated work
+        if job.is_none() && config.max_blocks.is_none_or(|max| mined < max) {
+            job = Some(MiningJob::start(&bc, &mut rng, config.name.clone(), &config.key, config.threads));
+        }
+    }
+
+    if let Some(job) = job {
+        job.cancel.cancel();
+        let _ = job.handle.join();
+    }
+    // Lets the network thread send our last blocks and stop
+    drop(tx2);
+    drop(rx1);
+    network.join().expect("Network thread panicked");
+    bc
+}
+
+/// Mines the blocks of the templates of the server reached through
+/// `transport`, until `max_blocks` blocks are accepted, without keeping a copy
+/// of the chain: the template tells which tip to extend and what to include.
+/// Stops if the server is on another network than `config.rules`, or refuses
+/// our miner name. Returns the number of blocks the server accepted.
+pub fn mine_from_templates<T: Transport>(config: &MinerConfig, mut transport: T) -> u64 {
+    let mut job: Option<MiningJob> = None;
+    let mut mined = 0;
+    let mut rng = thread_rng();
+
+    while config.max_blocks.is_none_or(|max| mined < max) {
+        let template = match transport.template(&config.name) {
+            Ok(template) => template,
+            Err(TransportError::Unavailable(e)) => {
+                println!("Cannot get a block template: {}", e);
+                thread::sleep(transport.backoff().max(TEMPLATE_REFRESH));
+                continue;
+            }
+            Err(e) => {
+                println!("Block template refused: {}", e);
+                break;
+            }
+        };
+        if template.network_id != config.rules.network_id() {
+            println!("The server is on another chain than ours!");
+            break;
+        }
+
+        // Stop working on a tip which is not the best one anymore
+        if job.as_ref().is_some_and(|job| job.parent_hash != template.parent_hash) {
+            println!("New best tip, restarting mining");
+            let stale = job.take().unwrap();
+            stale.cancel.cancel();
+            let _ = stale.handle.join();
+        }
+        let current = job.get_or_insert_with(|| MiningJob::from_template(&template, &mut rng, config));
+
+        // Until the next template, unless the block is solved earlier
+        let refresh = Instant::now() + TEMPLATE_REFRESH;
+        while !current.handle.is_finished() && Instant::now() < refresh {
+            thread::sleep(Duration::from_millis(10));
+        }
+        if !current.handle.is_finished() {
+            continue;
+        }
+        let finished = job.take().unwrap();
+        if let Some(new_block) = finished.handle.join().expect("Mining thread panicked") {
+            println!("Mined new block with dance move: {:?}, hash: {:?}", new_block.dancemove, new_block.hash_block());
+            match transport.submit(&new_block) {
+                Ok(()) => mined += 1,
+                Err(e) => println!("Block refused: {}", e),
+            }
+        }
+    }
+
+    if let Some(job) = job {
+        job.cancel.cancel();
+        let _ = job.handle.join();
+    }
+    mined
+}
+
+fn random_dance_move<R: RngCore>(rng: &mut R) -> DanceMove {
+    let dance_moves = [DanceMove::Y, DanceMove::M, DanceMove::C, DanceMove::A];
+    dance_moves[rng.next_u32() as usize % dance_moves.len()]
+}
+
+/// A block being solved in the background.
+struct MiningJob {
+    /// The tip the block extends.
+    parent_hash: Vec<u8>,
+    cancel: CancelHandle,
+    /// Returns the solved block, or None if cancelled.
+    handle: JoinHandle<Option<Block>>,
+}
+
+impl MiningJob {
+    fn start<R: RngCore>(
+        bc: &Blockchain,
+        rng: &mut R,
+        miner_name: String,
+        key: &SigningKey,
+        threads: usize,
+    ) -> Self {
+        let dancemove = random_dance_move(rng);
+        let parent_hash = bc.best_tip().to_vec();
+        let context = bc.next_context(&parent_hash).unwrap();
+
+        // Create the block, and solve it in the background
+        let mut new_block = Block::new(parent_hash.clone(), miner_name, 0, dancemove);
+        new_block.network_id = bc.rules().network_id().to_string();
+        new_block.timestamp = new_block.timestamp.max(context.median_time_past + 1);
+        new_block.difficulty = context.difficulty;
+        new_block.sign(key);
+        Self::solve(new_block, threads)
+    }
+
+    fn from_template<R: RngCore>(template: &BlockTemplate, rng: &mut R, config: &MinerConfig) -> Self {
+        let new_block = template.block(config.name.clone(), &config.key, random_dance_move(rng));
+        Self::solve(new_block, config.threads)
+    }
+
+    /// Solves a signed block in the background, at the difficulty it
+    /// commits to.
+    fn solve(mut new_block: Block, threads: usize) -> Self {
+        let parent_hash = new_block.parent_hash.clone();
+        let difficulty = new_block.difficulty;
+        let cancel = CancelHandle::new();
+        let job_cancel = cancel.clone();
+        let handle = thread::spawn(move || {
+            new_block
+                .solve_block_cancellable(&mut thread_rng(), difficulty, None, threads, &job_cancel)
+                .map(|_| new_block)
+        });
+
+        MiningJob {
+            parent_hash,
+            cancel,
+            handle,
+        }
+    }
+}
@@ -1,55 +1,836 @@
-use crate::Block;
+use crate::block::unix_time;
+use crate::block::Block;
+use crate::block::DanceMove;
+use crate::blockchain::BlockStatus;
+use crate::error::BlockValidationError;
+use crate::node::Node;
+use crate::pool::PoolTransport;
+use crate::pool::PoolWork;
+use crate::pool::Share;
+use crate::pool::ShareStatus;
+use crate::transaction::Transaction;
+use ed25519_dalek::SigningKey;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use std::fmt::Display;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::sync::mpsc;
 use std::sync::mpsc::Receiver;
+use std::sync::mpsc::RecvTimeoutError;
 use std::sync::mpsc::SyncSender;
+use std::sync::mpsc::TryRecvError;
+use std::sync::mpsc::TrySendError;
+use std::sync::{Arc, Mutex};
+use std::thread;
 use std::thread::sleep;
 use std::time::Duration;
+use std::time::Instant;
 
-const URL: &str = "http://localhost:8080";
+/// Server used when none is configured.
+pub const DEFAULT_SERVER: &str = "http://localhost:8080";
 const REQUEST_PAUSE_IN_SECONDS: u64 = 1;
+/// How long we wait for a server to answer before trying the next one.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Wait after every server failed once, doubled for each following round
+/// of failures up to `MAX_BACKOFF`.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// While following the block stream, how long we wait for an event before
+/// sending the blocks of the miner.
+const STREAM_WAIT: Duration = Duration::from_millis(100);
+/// The server pings the stream every 15 seconds, we drop it after this long
+/// without events.
+const STREAM_TIMEOUT: Duration = Duration::from_secs(45);
+/// How long we poll before trying to follow the block stream again.
+const STREAM_RETRY: Duration = Duration::from_secs(10);
 
-pub struct NetworkConnector {
+/// Answer of `GET /blocks?since=<cursor>`: the blocks the server accepted
+/// after the first `cursor` ones, in the order it accepted them.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BlockPage {
+    pub blocks: Vec<Block>,
+    /// Cursor of the next request.
+    pub next: u64,
+    /// Whether the server has more blocks than fit in this page.
+    pub more: bool,
+}
+
+/// Answer of `GET /template?miner=<name>`: everything a miner needs to mine
+/// the next block of the best chain of the server, without a copy of the
+/// chain.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BlockTemplate {
+    pub network_id: String,
+    /// Hash of the best tip, the parent of the block.
+    pub parent_hash: Vec<u8>,
+    /// Height of the block.
+    pub height: u64,
+    /// Difficulty the block must commit to.
+    pub difficulty: u32,
+    /// Earliest timestamp of the block: one second after the median time
+    /// past of its ancestors.
+    pub min_timestamp: u64,
+    /// Latest timestamp of the block the server accepts now.
+    pub max_timestamp: u64,
+    /// Pending transactions the block can include, in this order.
+    pub transactions: Vec<Transaction>,
+    /// Reward of the miner, on top of the fees of the transactions.
+    pub reward: u64,
+}
+
+impl BlockTemplate {
+    /// Creates the block of the template, dated now within the bounds of
+    /// the template, and signs it. It is left to solve.
+    pub fn block(&self, miner_name: String, key: &SigningKey, dancemove: DanceMove) -> Block {
+        let mut block = Block::new(self.parent_hash.clone(), miner_name, 0, dancemove);
+        block.network_id = self.network_id.clone();
+        block.timestamp = unix_time().clamp(self.min_timestamp, self.max_timestamp.max(self.min_timestamp));
+        block.difficulty = self.difficulty;
+        block.set_transactions(self.transactions.clone());
+        block.sign(key);
+        block
+    }
+}
+
+/// The servers we can talk to. Requests go to the current server, and move
+/// on to the next one when it fails.
+#[derive(Debug)]
+pub struct Endpoints {
+    urls: Vec<String>,
+    current: usize,
+    /// Failed requests since the last successful one.
+    failures: u32,
+    /// Whether the last request to the current server succeeded.
+    healthy: bool,
+}
+
+impl Endpoints {
+    /// Builds the list from server URLs, such as `http://localhost:8080`.
+    /// Uses `DEFAULT_SERVER` if `urls` is empty.
+    pub fn new<I, S>(urls: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let mut urls: Vec<String> = urls
+            .into_iter()
+            .map(|url| url.into().trim_end_matches('/').to_string())
+            .filter(|url| !url.is_empty())
+            .collect();
+        if urls.is_empty() {
+            urls.push(DEFAULT_SERVER.to_string());
+        }
+        Endpoints {
+            urls,
+            current: 0,
+            failures: 0,
+            healthy: false,
+        }
+    }
+
+    /// The server the next request goes to.
+    pub fn current(&self) -> &str {
+        &self.urls[self.current]
+    }
+
+    /// The server which answered the last request, if it succeeded.
+    pub fn healthy(&self) -> Option<&str> {
+        self.healthy.then(|| self.current())
+    }
+
+    /// Runs `request` with the URL of a server, trying each server at most
+    /// once, starting with the current one. Returns the first success, or
+    /// the last error if every server failed.
+    pub fn request<T, E, F>(&mut self, mut request: F) -> Result<T, E>
+    where
+        E: Display,
+        F: FnMut(&str) -> Result<T, E>,
+    {
+        let mut attempts = self.urls.len();
+        loop {
+            match request(self.current()) {
+                Ok(result) => {
+                    if !self.healthy {
+                        println!("Using server {}", self.current());
+                    }
+                    self.healthy = true;
+                    self.failures = 0;
+                    return Ok(result);
+                }
+                Err(e) => {
+                    println!("Server {} failed: {}", self.current(), e);
+                    self.healthy = false;
+                    self.failures = self.failures.saturating_add(1);
+                    self.current = (self.current + 1) % self.urls.len();
+                    attempts -= 1;
+                    if attempts == 0 {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// How long to wait before the next request: nothing while some server
+    /// has not failed yet, then `INITIAL_BACKOFF` doubled for every round of
+    /// failures of all the servers.
+    pub fn backoff(&self) -> Duration {
+        let rounds = self.failures / self.urls.len() as u32;
+        if rounds == 0 {
+            return Duration::ZERO;
+        }
+        INITIAL_BACKOFF
+            .checked_mul(1 << (rounds - 1).min(16))
+            .unwrap_or(MAX_BACKOFF)
+            .min(MAX_BACKOFF)
+    }
+}
+
+/// Event of a stream of accepted blocks, such as `GET /events`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StreamEvent {
+    /// A block the server accepted, and the cursor following it.
+    Block(u64, Box<Block>),
+    /// Sent when no block was accepted for a while, to tell that the stream
+    /// is still alive.
+    Ping,
+}
+
+/// Error of a [`Transport`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransportError {
+    /// No server answered. The request can be retried later.
+    Unavailable(String),
+    /// The server refused the request.
+    Rejected(String),
+    /// The server refused the block as invalid.
+    Invalid(BlockValidationError),
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransportError::Unavailable(e) => write!(f, "no server available: {}", e),
+            TransportError::Rejected(e) => write!(f, "rejected: {}", e),
+            TransportError::Invalid(e) => write!(f, "invalid block: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for TransportError {}
+
+impl From<reqwest::Error> for TransportError {
+    fn from(e: reqwest::Error) -> Self {
+        TransportError::Unavailable(e.to_string())
+    }
+}
+
+/// How a miner talks to a server.
+pub trait Transport {
+    /// Blocks the server accepted after the first `since` ones.
+    fn fetch(&mut self, since: u64) -> Result<BlockPage, TransportError>;
+
+    /// Sends a block we mined.
+    fn submit(&mut self, block: &Block) -> Result<(), TransportError>;
+
+    /// Starts receiving the blocks the server accepts from now on, from the
+    /// server whose cursors `fetch` returns.
+    fn subscribe(&mut self) -> Result<Receiver<StreamEvent>, TransportError>;
+
+    /// Whether the stream of the last `subscribe` comes from the server
+    /// whose cursors `fetch` returns. When `fetch` moves to another server,
+    /// the ids of the stream are not our cursors anymore.
+    fn stream_follows_cursor(&self) -> bool {
+        true
+    }
+
+    /// Template of the next block of the best chain, for the miner named
+    /// `miner`.
+    fn template(&mut self, miner: &str) -> Result<BlockTemplate, TransportError>;
+
+    /// How long to wait before retrying after the server was unavailable.
+    fn backoff(&self) -> Duration {
+        Duration::ZERO
+    }
+
+    /// Time after which a silent stream is considered lost, if the server
+    /// pings it.
+    fn stream_timeout(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Reads the next server-sent event, or None at the end of the stream.
+/// Events we do not know are skipped.
+fn read_event<R: BufRead>(reader: &mut R) -> io::Result<Option<StreamEvent>> {
+    let (mut id, mut event, mut data) = (None, String::new(), String::new());
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            // End of an event
+            match (event.as_str(), id) {
+                ("block", Some(id)) => {
+                    let block = serde_json::from_str(&data)?;
+                    return Ok(Some(StreamEvent::Block(id, block)));
+                }
+                ("ping", _) => return Ok(Some(StreamEvent::Ping)),
+                _ => {
+                    (id, event, data) = (None, String::new(), String::new());
+                    continue;
+                }
+            }
+        }
+        // Lines starting with ':' are comments, their field is empty
+        let (field, value) = line.split_once(':').unwrap_or((line, ""));
+        let value = value.strip_prefix(' ').unwrap_or(value);
+        match field {
+            "id" => id = value.parse().ok(),
+            "event" => event = value.to_string(),
+            "data" => data.push_str(value),
+            _ => {}
+        }
+    }
+}
+
+fn client() -> reqwest::Result<reqwest::blocking::Client> {
+    reqwest::blocking::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+}
+
+/// Transport to servers over HTTP, failing over between `endpoints`.
+pub struct HttpTransport {
+    endpoints: Endpoints,
+    client: reqwest::blocking::Client,
+    /// Server which gave the last cursor. Cursors of different servers are
+    /// unrelated, we restart from 0 when switching.
+    cursor_server: String,
+    /// Server whose block stream we subscribed to last.
+    stream_server: String,
+}
+
+impl HttpTransport {
+    pub fn new(endpoints: Endpoints) -> reqwest::Result<Self> {
+        Ok(HttpTransport {
+            endpoints,
+            client: client()?,
+            cursor_server: String::new(),
+            stream_server: String::new(),
+        })
+    }
+}
+
+impl Transport for HttpTransport {
+    fn fetch(&mut self, since: u64) -> Result<BlockPage, TransportError> {
+        let (client, cursor_server) = (&self.client, &self.cursor_server);
+        let (url, page) = self.endpoints.request(|url| {
+            let since = if url == cursor_server { since } else { 0 };
+            let page: BlockPage = client
+                .get(format!("{}/blocks", url))
+                .query(&[("since", since)])
+                .send()?
+                .error_for_status()?
+                .json()?;
+            Ok::<_, reqwest::Error>((url.to_string(), page))
+        })?;
+        self.cursor_server = url;
+        Ok(page)
+    }
+
+    fn submit(&mut self, block: &Block) -> Result<(), TransportError> {
+        let client = &self.client;
+        let resp = self.endpoints.request(|url| {
+            let resp = client.post(format!("{}/postblock", url)).json(block).send()?;
+            // Client errors are about the block, not the server
+            if resp.status().is_server_error() {
+                resp.error_for_status()
+            } else {
+                Ok(resp)
+            }
+        })?;
+        if resp.status().is_client_error() {
+            let body = resp.text()?;
+            // Invalid blocks are explained in JSON, other refusals in text
+            return Err(match serde_json::from_str(&body) {
+                Ok(e) => TransportError::Invalid(e),
+                Err(_) => TransportError::Rejected(body),
+            });
+        }
+        Ok(())
+    }
+
+    fn template(&mut self, miner: &str) -> Result<BlockTemplate, TransportError> {
+        let client = &self.client;
+        let resp = self.endpoints.request(|url| {
+            let resp = client.get(format!("{}/template", url)).query(&[("miner", miner)]).send()?;
+            // Client errors are about the miner, not the server
+            if resp.status().is_server_error() {
+                resp.error_for_status()
+            } else {
+                Ok(resp)
+            }
+        })?;
+        if resp.status().is_client_error() {
+            let body = resp.text()?;
+            // A forbidden miner name is explained in JSON, like invalid blocks
+            return Err(match serde_json::from_str(&body) {
+                Ok(e) => TransportError::Invalid(e),
+                Err(_) => TransportError::Rejected(body),
+            });
+        }
+        Ok(resp.json()?)
+    }
+
+    /// Follows `GET /events` of the server which gave the last cursor.
+    fn subscribe(&mut self) -> Result<Receiver<StreamEvent>, TransportError> {
+        if self.cursor_server.is_empty() {
+            return Err(TransportError::Unavailable("no server gave a cursor yet".to_string()));
+        }
+        let url = &self.cursor_server;
+        // No timeout: the stream lasts as long as the server
+        let client = reqwest::blocking::Client::builder()
+            .connect_timeout(REQUEST_TIMEOUT)
+            .timeout(None)
+            .build()?;
+        let resp = client.get(format!("{}/events", url)).send()?.error_for_status()?;
+        self.stream_server = url.clone();
+        let (tx, events) = mpsc::channel();
+        thread::spawn(move || {
+            let mut reader = BufReader::new(resp);
+            // Stops when the stream ends or fails, or when we are not
+            // listening anymore
+            while let Ok(Some(event)) = read_event(&mut reader) {
+                if tx.send(event).is_err() {
+                    return;
+                }
+            }
+        });
+        Ok(events)
+    }
+
+    fn stream_follows_cursor(&self) -> bool {
+        self.stream_server == self.cursor_server
+    }
+
+    fn backoff(&self) -> Duration {
+        self.endpoints.backoff()
+    }
+
+    fn stream_timeout(&self) -> Option<Duration> {
+        Some(STREAM_TIMEOUT)
+    }
+}
+
+/// The pool of a server started with a pool key.
+impl PoolTransport for HttpTransport {
+    fn work(&mut self) -> Result<PoolWork, TransportError> {
+        let client = &self.client;
+        let resp = self.endpoints.request(|url| {
+            let resp = client.get(format!("{}/pool/work", url)).send()?;
+            // Servers without a pool do not know the route
+            if resp.status().is_server_error() {
+                resp.error_for_status()
+            } else {
+                Ok(resp)
+            }
+        })?;
+        if resp.status().is_client_error() {
+            return Err(TransportError::Rejected(format!("no pool: {}", resp.status())));
+        }
+        Ok(resp.json()?)
+    }
+
+    fn submit_share(&mut self, share: &Share) -> Result<ShareStatus, TransportError> {
+        let client = &self.client;
+        let resp = self.endpoints.request(|url| {
+            let resp = client.post(format!("{}/pool/share", url)).json(share).send()?;
+            // Client errors are about the share, not the pool
+            if resp.status().is_server_error() {
+                resp.error_for_status()
+            } else {
+                Ok(resp)
+            }
+        })?;
+        if resp.status().is_client_error() {
+            return Err(TransportError::Rejected(resp.text()?));
+        }
+        Ok(resp.json()?)
+    }
+
+    fn backoff(&self) -> Duration {
+        self.endpoints.backoff()
+    }
+}
+
+/// Transport to a [`Node`] of the same process, to run miners and a server
+/// in tests and simulations.
+#[derive(Clone)]
+pub struct LocalTransport {
+    node: Arc<Mutex<Node>>,
+}
+
+impl LocalTransport {
+    pub fn new(node: Arc<Mutex<Node>>) -> Self {
+        LocalTransport { node }
+    }
+}
+
+impl Transport for LocalTransport {
+    fn fetch(&mut self, since: u64) -> Result<BlockPage, TransportError> {
+        Ok(self.node.lock().unwrap().page(since))
+    }
+
+    fn submit(&mut self, block: &Block) -> Result<(), TransportError> {
+        match self.node.lock().unwrap().submit(block.clone()) {
+            Ok(BlockStatus::Connected(_)) | Ok(BlockStatus::Orphaned) => Ok(()),
+            Ok(BlockStatus::Duplicate) => Err(TransportError::Rejected("Block already exists".to_string())),
+            Err(e) => Err(TransportError::Invalid(e)),
+        }
+    }
+
+    fn subscribe(&mut self) -> Result<Receiver<StreamEvent>, TransportError> {
+        Ok(self.node.lock().unwrap().subscribe())
+    }
+
+    fn template(&mut self, miner: &str) -> Result<BlockTemplate, TransportError> {
+        // A node alone has no pending transactions
+        self.node
+            .lock()
+            .unwrap()
+            .template(miner, Vec::new())
+            .map_err(TransportError::Invalid)
+    }
+}
+
+/// Stream of accepted blocks we follow.
+struct Subscription {
+    events: Receiver<StreamEvent>,
+    last_event: Instant,
+}
+
+pub struct NetworkConnector<T: Transport> {
     // channel to send new blocks received from the server
     tx: SyncSender<Vec<Block>>,
     // channel to receive a new block from the miner.
     rx: Receiver<Block>,
+    transport: T,
+    /// Our cursor in the blocks of the server.
+    cursor: u64,
+    /// Blocks received from the server the miner has not taken yet.
+    received: Vec<Block>,
+    /// Stream of the blocks accepted by the server, if we follow it. We poll
+    /// the server otherwise.
+    subscription: Option<Subscription>,
+    /// When to try following the block stream again.
+    next_subscription: Instant,
 }
 
-impl NetworkConnector {
-    pub fn new(tx: SyncSender<Vec<Block>>, rx: Receiver<Block>) -> Self {
-        NetworkConnector { tx, rx }
+impl<T: Transport> NetworkConnector<T> {
+    pub fn new(tx: SyncSender<Vec<Block>>, rx: Receiver<Block>, transport: T) -> Self {
+        NetworkConnector {
+            tx,
+            rx,
+            transport,
+            cursor: 0,
+            received: Vec::new(),
+            subscription: None,
+            next_subscription: Instant::now(),
+        }
     }
 
-    pub fn sync(&mut self) -> reqwest::Result<()> {
-        let mut fullurlpost: String = URL.to_owned();
-        fullurlpost.push_str("/postblock");
-        let client = reqwest::blocking::Client::new();
+    /// Sends the blocks of the miner to the server and the blocks of the
+    /// server to the miner, until the miner stops.
+    pub fn sync(&mut self) {
+        // Blocks of the miner not sent yet, kept while no server is reachable
+        let mut outgoing = VecDeque::new();
 
         loop {
             // Check if the miner wants us to send any block
-            if let Ok(block) = self.rx.try_recv() {
-                let resp = client.post(&fullurlpost).json(&block).send()?;
+            let stopped = loop {
+                match self.rx.try_recv() {
+                    Ok(block) => outgoing.push_back(block),
+                    Err(TryRecvError::Empty) => break false,
+                    Err(TryRecvError::Disconnected) => break true,
+                }
+            };
+            let mut reachable = true;
+            while let Some(block) = outgoing.front() {
+                match self.transport.submit(block) {
+                    Ok(()) => {}
+                    Err(TransportError::Rejected(e)) => println!("An error occured: {:?}", e),
+                    Err(TransportError::Invalid(e)) => println!("Block refused ({}): {}", e.code(), e),
+                    Err(TransportError::Unavailable(_)) => {
+                        reachable = false;
+                        break;
+                    }
+                }
+                outgoing.pop_front();
+            }
+            if stopped {
+                return;
+            }
 
-                if resp.status().is_client_error() {
-                    println!("An error occured: {:?}", resp.text()?)
+            if self.subscription.is_none() {
+                if reachable {
+                    self.catch_up();
+                }
+                // Fetches again what was accepted before the stream started
+                if self.subscribe() {
+                    self.catch_up();
                 }
+            } else {
+                self.follow_stream();
             }
-            // Recover all the blocks known by the server and send
-            // them to the miner.
-            match get_blocks() {
-                // If the miner did not consume previous blocks; we drop them
-                // and retry in one second.
-                Ok(blocks) => self.tx.try_send(blocks).unwrap_or(()),
-                Err(e) => println!("Request failed {:?}", e),
-            };
 
-            sleep(Duration::from_secs(REQUEST_PAUSE_IN_SECONDS));
+            if !self.received.is_empty() {
+                // If the miner did not consume previous blocks, we keep them
+                // and retry later.
+                match self.tx.try_send(std::mem::take(&mut self.received)) {
+                    Err(TrySendError::Full(blocks)) => self.received = blocks,
+                    Ok(()) | Err(TrySendError::Disconnected(_)) => {}
+                }
+            }
+
+            if self.subscription.is_none() {
+                let backoff = self.transport.backoff();
+                if !backoff.is_zero() {
+                    println!("No server reachable, retrying in {:?}", backoff);
+                }
+                sleep(backoff.max(Duration::from_secs(REQUEST_PAUSE_IN_SECONDS)));
+            }
         }
     }
+
+    /// Starts following the block stream of the server, if it is time to
+    /// try. Returns whether it started.
+    fn subscribe(&mut self) -> bool {
+        if Instant::now() < self.next_subscription {
+            return false;
+        }
+        match self.transport.subscribe() {
+            Ok(events) => {
+                println!("Following the blocks accepted by the server");
+                self.subscription = Some(Subscription {
+                    events,
+                    last_event: Instant::now(),
+                });
+                true
+            }
+            Err(e) => {
+                println!("Cannot follow the blocks of the server, polling: {}", e);
+                self.next_subscription = Instant::now() + STREAM_RETRY;
+                false
+            }
+        }
+    }
+
+    /// Waits for the next event of the block stream, falling back to polling
+    /// when the stream drops.
+    fn follow_stream(&mut self) {
+        let timeout = self.transport.stream_timeout();
+        let subscription = self.subscription.as_mut().unwrap();
+        match subscription.events.recv_timeout(STREAM_WAIT) {
+            Ok(StreamEvent::Block(id, block)) => {
+                subscription.last_event = Instant::now();
+                if id > self.cursor + 1 {
+                    // We missed blocks
+                    self.catch_up();
+                } else if id == self.cursor + 1 {
+                    self.cursor = id;
+                    self.received.push(*block);
+                }
+            }
+            Ok(StreamEvent::Ping) => subscription.last_event = Instant::now(),
+            Err(RecvTimeoutError::Timeout)
+                if timeout.is_none_or(|timeout| subscription.last_event.elapsed() < timeout) => {}
+            Err(_) => {
+                println!("Lost the block stream of the server, polling");
+                self.subscription = None;
+            }
+        }
+    }
+
+    /// Fetches every block accepted since our cursor. Drops the block stream
+    /// if the cursor now comes from another server, to follow the stream of
+    /// that server instead.
+    fn catch_up(&mut self) {
+        self.fetch_since_cursor();
+        if self.subscription.is_some() && !self.transport.stream_follows_cursor() {
+            println!("Switched servers, following the block stream of the new one");
+            self.subscription = None;
+            self.next_subscription = Instant::now();
+        }
+    }
+
+    fn fetch_since_cursor(&mut self) {
+        loop {
+            match self.transport.fetch(self.cursor) {
+                Ok(page) => {
+                    self.received.extend(page.blocks);
+                    self.cursor = page.next;
+                    if !page.more {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    println!("Request failed {}", e);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Downloads every block known by the first server of `endpoints` which
+/// answers.
+pub fn get_blocks(endpoints: &mut Endpoints) -> reqwest::Result<Vec<Block>> {
+    let client = client()?;
+    endpoints.request(|url| {
+        client
+            .get(format!("{}/blocks", url))
+            .send()?
+            .error_for_status()?
+            .json()
+    })
 }
 
-pub fn get_blocks() -> reqwest::Result<Vec<Block>> {
-    let mut fullurl: String = URL.to_owned();
-    fullurl.push_str("/blocks");
-    let blocks: Vec<Block> = reqwest::blocking::get(fullurl)?.json()?;
-    Ok(blocks)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_endpoints() {
+        let endpoints = Endpoints::new(Vec::<String>::new());
+        assert_eq!(endpoints.current(), DEFAULT_SERVER);
+        assert_eq!(endpoints.healthy(), None);
+
+        let mut endpoints = Endpoints::new(["http://a/", "http://b", "http://c"]);
+        assert_eq!(endpoints.current(), "http://a");
+
+        // Failing servers are skipped
+        let mut tried = Vec::new();
+        let result: Result<&str, &str> = endpoints.request(|url| {
+            tried.push(url.to_string());
+            if url == "http://c" { Ok("blocks") } else { Err("down") }
+        });
+        assert_eq!(result, Ok("blocks"));
+        assert_eq!(tried, ["http://a", "http://b", "http://c"]);
+        assert_eq!(endpoints.healthy(), Some("http://c"));
+        assert_eq!(endpoints.backoff(), Duration::ZERO);
+
+        // Following requests stay on the healthy server
+        let result: Result<(), &str> = endpoints.request(|url| {
+            assert_eq!(url, "http://c");
+            Ok(())
+        });
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_backoff() {
+        let mut endpoints = Endpoints::new(["http://a", "http://b"]);
+
+        // Each request tries every server once
+        endpoints.request(|_| Err::<(), _>("down")).unwrap_err();
+        let mut expected = INITIAL_BACKOFF;
+        for _ in 0..10 {
+            assert_eq!(endpoints.backoff(), expected);
+            assert_eq!(endpoints.healthy(), None);
+            endpoints.request(|_| Err::<(), _>("down")).unwrap_err();
+            expected = (expected * 2).min(MAX_BACKOFF);
+        }
+        assert_eq!(endpoints.backoff(), MAX_BACKOFF);
+
+        // A success resets the backoff
+        endpoints.request(|_| Ok::<_, &str>(())).unwrap();
+        assert_eq!(endpoints.backoff(), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_read_event() {
+        let block = Block::new(vec![1, 2], "miner".to_string(), 3, crate::block::DanceMove::A);
+        let stream = format!(
+            ":{}\nid: 5\nevent: block\ndata: {}\n\n\
+             event: unknown\ndata: x\n\n\
+             event: ping\r\ndata:\r\n\r\n\
+             id: 6\nevent: block\ndata: {{\n",
+            " ".repeat(100),
+            serde_json::to_string(&block).unwrap()
+        );
+        let mut reader = stream.as_bytes();
+        assert_eq!(read_event(&mut reader).unwrap(), Some(StreamEvent::Block(5, Box::new(block))));
+        assert_eq!(read_event(&mut reader).unwrap(), Some(StreamEvent::Ping));
+        // The last event is cut
+        assert_eq!(read_event(&mut reader).unwrap(), None);
+
+        let mut reader = "event: block\nid: 1\ndata: {\n\n".as_bytes();
+        assert!(read_event(&mut reader).is_err());
+    }
+
+    /// Servers answering in turn, the cursor of each request coming from the
+    /// next one.
+    struct FailoverTransport {
+        servers: Vec<&'static str>,
+        cursor_server: usize,
+        stream_server: usize,
+        subscriptions: usize,
+    }
+
+    impl Transport for FailoverTransport {
+        fn fetch(&mut self, _since: u64) -> Result<BlockPage, TransportError> {
+            self.cursor_server = (self.cursor_server + 1) % self.servers.len();
+            Ok(BlockPage {
+                blocks: Vec::new(),
+                next: 0,
+                more: false,
+            })
+        }
+
+        fn submit(&mut self, _block: &Block) -> Result<(), TransportError> {
+            Ok(())
+        }
+
+        fn subscribe(&mut self) -> Result<Receiver<StreamEvent>, TransportError> {
+            self.stream_server = self.cursor_server;
+            self.subscriptions += 1;
+            Ok(mpsc::channel().1)
+        }
+
+        fn stream_follows_cursor(&self) -> bool {
+            self.stream_server == self.cursor_server
+        }
+
+        fn template(&mut self, _miner: &str) -> Result<BlockTemplate, TransportError> {
+            Err(TransportError::Rejected("no templates".to_string()))
+        }
+    }
+
+    #[test]
+    fn test_stream_follows_cursor_server() {
+        let transport = FailoverTransport {
+            servers: vec!["http://a", "http://b"],
+            cursor_server: 0,
+            stream_server: 0,
+            subscriptions: 0,
+        };
+        let (tx, _) = mpsc::sync_channel(1);
+        let (_, rx) = mpsc::channel();
+        let mut connector = NetworkConnector::new(tx, rx, transport);
+        assert!(connector.subscribe());
+
+        // Catching up on another server drops the stream of the first one
+        connector.catch_up();
+        assert!(connector.subscription.is_none());
+        assert!(connector.subscribe());
+        assert_eq!(connector.transport.subscriptions, 2);
+        assert_eq!(connector.transport.stream_server, 1);
+    }
 }
@@ -0,0 +1,255 @@
+use crate::block::unix_time;
+use crate::block::Block;
+use crate::block::DanceMove;
+use crate::block::MAX_FUTURE_BLOCK_TIME;
+use crate::blockchain::BlockStatus;
+use crate::blockchain::Blockchain;
+use crate::consensus::ConsensusRules;
+use crate::consensus::DefaultRules;
+use crate::error::BlockValidationError;
+use crate::network::BlockPage;
+use crate::network::BlockTemplate;
+use crate::network::StreamEvent;
+use crate::transaction::Transaction;
+use std::sync::mpsc;
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::Arc;
+
+/// Maximum number of blocks of a [`BlockPage`].
+pub const MAX_PAGE_BLOCKS: usize = 500;
+
+/// The blocks accepted by a server and the rules it accepts them with,
+/// independent of how miners reach it.
+#[derive(Debug)]
+pub struct Node {
+    /// Minimum difficulty of the blocks we accept.
+    min_difficulty: u32,
+    /// Hashes of the blocks connected to the chain, genesis included, in the
+    /// order they were connected. The cursor of a [`BlockPage`] is an index
+    /// in this list.
+    order: Vec<[u8; 32]>,
+    /// The tree of blocks, used to check that new blocks extend it, and
+    /// holding the blocks of `order`.
+    chain: Blockchain,
+    /// Receivers of the blocks we accept.
+    subscribers: Vec<Sender<StreamEvent>>,
+}
+
+impl Node {
+    pub fn new(genesis: Block, min_difficulty: u32) -> Self {
+        Self::with_rules(genesis, min_difficulty, Arc::new(DefaultRules))
+    }
+
+    /// Creates a node accepting blocks under the given rules.
+    pub fn with_rules(genesis: Block, min_difficulty: u32, rules: Arc<dyn ConsensusRules>) -> Self {
+        let mut node = Node {
+            min_difficulty,
+            order: Vec::new(),
+            chain: Blockchain::with_rules(genesis.clone(), rules),
+            subscribers: Vec::new(),
+        };
+        node.insert(genesis.hash_block());
+        node
+    }
+
+    /// Accepts the valid blocks of `blocks` without checking the minimum
+    /// difficulty, such as blocks we stored before a restart. Returns the
+    /// number of blocks connected.
+    pub fn restore(&mut self, blocks: Vec<Block>) -> usize {
+        let hashes = self.chain.add_blocks(blocks);
+        for hash in &hashes {
+            self.insert(hash.as_slice().try_into().unwrap());
+        }
+        hashes.len()
+    }
+
+    /// Checks a block from a miner and adds it to the chain. A block whose
+    /// parent is unknown is parked until the parent shows up.
+    pub fn submit(&mut self, block: Block) -> Result<BlockStatus, BlockValidationError> {
+        self.submit_with(block, |_| Ok(()))
+    }
+
+    /// Same as [`Node::submit`], calling `store` with each block before it
+    /// is connected and sent to subscribers, as
+    /// [`Blockchain::add_block_with`] does.
+    pub fn submit_with<E, F>(&mut self, block: Block, store: F) -> Result<BlockStatus, E>
+    where
+        E: From<BlockValidationError>,
+        F: FnMut(&Block) -> Result<(), E>,
+    {
+        match self.chain.get(&block.hash_block()) {
+            Some(known) if *known == block => return Ok(BlockStatus::Duplicate),
+            Some(_) => return Err(BlockValidationError::HashCollision.into()),
+            None => {}
+        }
+        self.chain.rules().validate_header(&block, self.min_difficulty)?;
+
+        let status = self.chain.add_block_with(block, store)?;
+        if let BlockStatus::Connected(hashes) = &status {
+            // The block may have connected orphans waiting on it
+            for hash in hashes {
+                self.insert(hash.as_slice().try_into().unwrap());
+            }
+        }
+        Ok(status)
+    }
+
+    /// Records a block connected to the chain
+    fn insert(&mut self, hash: [u8; 32]) {
+        self.order.push(hash);
+        let cursor = self.order.len() as u64;
+        let block = self.chain.get(&hash).expect("connected blocks are in the chain");
+        self.subscribers
+            .retain(|tx| tx.send(StreamEvent::Block(cursor, Box::new(block.clone()))).is_ok());
+    }
+
+    pub fn get(&self, hash: &[u8; 32]) -> Option<&Block> {
+        self.chain.get(hash)
+    }
+
+    /// The accepted blocks, genesis included, in the order they were
+    /// connected.
+    pub fn blocks(&self) -> impl Iterator<Item = &Block> {
+        self.order.iter().map(|hash| self.get(hash).expect("connected blocks are in the chain"))
+    }
+
+    /// Number of accepted blocks, genesis included.
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    pub fn chain(&self) -> &Blockchain {
+        &self.chain
+    }
+
+    /// Blocks connected after the first `since` ones. A cursor past the end,
+    /// given by another server or before a crash lost blocks, starts over.
+    pub fn page(&self, since: u64) -> BlockPage {
+        let since = match usize::try_from(since) {
+            Ok(since) if since <= self.order.len() => since,
+            _ => 0,
+        };
+        let end = self.order.len().min(since + MAX_PAGE_BLOCKS);
+        BlockPage {
+            blocks: self.order[since..end].iter().map(|hash| self.get(hash).unwrap().clone()).collect(),
+            next: end as u64,
+            more: end < self.order.len(),
+        }
+    }
+
+    /// Template of the next block of the best chain for the miner named
+    /// `miner`, including `transactions`, which must apply in this order
+    /// after the best chain. Fails if the name is forbidden.
+    pub fn template(&self, miner: &str, transactions: Vec<Transaction>) -> Result<BlockTemplate, BlockValidationError> {
+        let parent_hash = self.chain.best_tip().to_vec();
+        let rules = self.chain.rules();
+        rules.check_miner_name(&Block::new(parent_hash.clone(), miner.to_string(), 0, DanceMove::Y))?;
+        let context = self.chain.next_context(&parent_hash).unwrap();
+        let height = self.chain.height(&parent_hash).unwrap() + 1;
+        Ok(BlockTemplate {
+            network_id: rules.network_id().to_string(),
+            height,
+            difficulty: context.difficulty,
+            min_timestamp: context.median_time_past + 1,
+            max_timestamp: unix_time() + MAX_FUTURE_BLOCK_TIME,
+            transactions,
+            reward: rules.block_reward(height),
+            parent_hash,
+        })
+    }
+
+    /// Returns a receiver of the blocks accepted from now on, with the cursor
+    /// following each of them.
+    pub fn subscribe(&mut self) -> Receiver<StreamEvent> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.push(tx);
+        rx
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::DanceMove;
+    use crate::block::TARGET_BLOCK_INTERVAL;
+    use ed25519_dalek::SigningKey;
+
+    fn genesis() -> Block {
+        let mut genesis = Block::new(Vec::new(), "Genesis".to_string(), 0, DanceMove::Y);
+        genesis.difficulty = 1;
+        genesis.timestamp = 1;
+        while !genesis.is_genesis(1) {
+            genesis.nonce += 1;
+        }
+        genesis
+    }
+
+    fn child(parent: &Block, nonce: u64) -> Block {
+        let mut block = Block::new(parent.hash_block().to_vec(), "miner".to_string(), nonce, DanceMove::M);
+        block.difficulty = 1;
+        // On time, so that the difficulty stays the same
+        block.timestamp = parent.timestamp + TARGET_BLOCK_INTERVAL;
+        block.sign(&SigningKey::from_bytes(&[7; 32]));
+        block.solve_block(&mut rand::thread_rng(), 1, None);
+        block
+    }
+
+    #[test]
+    fn test_submit() {
+        let genesis = genesis();
+        let mut node = Node::new(genesis.clone(), 1);
+        let events = node.subscribe();
+
+        let a = child(&genesis, 1);
+        let b = child(&a, 2);
+        assert_eq!(node.submit(b.clone()), Ok(BlockStatus::Orphaned));
+        assert_eq!(node.len(), 1);
+        assert_eq!(
+            node.submit(a.clone()),
+            Ok(BlockStatus::Connected(vec![a.hash_block().to_vec(), b.hash_block().to_vec()]))
+        );
+        assert_eq!(node.submit(a.clone()), Ok(BlockStatus::Duplicate));
+        assert_eq!(node.blocks().cloned().collect::<Vec<_>>(), vec![genesis.clone(), a.clone(), b.clone()]);
+        assert_eq!(
+            events.try_iter().collect::<Vec<_>>(),
+            vec![StreamEvent::Block(2, Box::new(a)), StreamEvent::Block(3, Box::new(b))]
+        );
+
+        // Blocks below the minimum difficulty are refused
+        let mut node = Node::new(genesis.clone(), 2);
+        assert_eq!(
+            node.submit(child(&genesis, 3)),
+            Err(BlockValidationError::DifficultyTooLow {
+                difficulty: 1,
+                min_difficulty: 2
+            })
+        );
+    }
+
+    #[test]
+    fn test_page() {
+        let genesis = genesis();
+        let mut node = Node::new(genesis.clone(), 0);
+        let mut all = vec![genesis];
+        for nonce in 0..MAX_PAGE_BLOCKS as u64 + 10 {
+            all.push(child(all.last().unwrap(), nonce));
+        }
+        assert_eq!(node.restore(all[1..].to_vec()), all.len() - 1);
+
+        let page = node.page(0);
+        assert_eq!(page.blocks, all[..MAX_PAGE_BLOCKS]);
+        assert!(page.more);
+        let page = node.page(page.next);
+        assert_eq!(page.blocks, all[MAX_PAGE_BLOCKS..]);
+        assert_eq!(page.next, all.len() as u64);
+        assert!(!page.more);
+        assert!(node.page(page.next).blocks.is_empty());
+
+        // Unknown cursors start over
+        assert_eq!(node.page(page.next + 1).blocks, all[..MAX_PAGE_BLOCKS]);
+    }
+}
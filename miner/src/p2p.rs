@@ -0,0 +1,388 @@
+use crate::block::Block;
+use crate::blockchain::BlockStatus;
+use crate::network::LocalTransport;
+use crate::network::StreamEvent;
+use crate::node::Node;
+use crate::node::MAX_PAGE_BLOCKS;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::sync::mpsc::{Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Address peers listen on unless told otherwise.
+pub const DEFAULT_LISTEN: &str = "0.0.0.0:9000";
+/// How often we try to reconnect to the configured peers we lost.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+/// A peer which does not take our messages for this long is dropped.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Messages waiting to be written to a peer. A peer falling further behind
+/// is dropped.
+const MAX_QUEUED_MESSAGES: usize = 1024;
+/// Longest message, newline excluded. A peer sending a longer line is
+/// dropped. Holds a full block with room to spare.
+const MAX_MESSAGE_LEN: usize = 16 * 1024 * 1024;
+
+/// Messages between peers, one JSON object per line.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Message {
+    /// Blocks the sender has connected to its chain.
+    Inv { hashes: Vec<[u8; 32]> },
+    /// Asks for the blocks with these hashes.
+    GetBlocks { hashes: Vec<[u8; 32]> },
+    /// Answer to `GetBlocks`, with the blocks the sender knows.
+    Blocks { blocks: Vec<Block> },
+}
+
+/// State shared by the threads of a peer.
+struct Shared {
+    node: Arc<Mutex<Node>>,
+    /// Queues of the messages to write to the open connections, by
+    /// connection id. Each connection has its own writing thread, so that a
+    /// slow peer does not hold up the others.
+    connections: Mutex<HashMap<u64, SyncSender<Arc<[u8]>>>>,
+    next_id: AtomicU64,
+}
+
+/// A node of the gossip network: it keeps its own copy of the chain in
+/// `node`, announces the blocks it connects to its peers by hash, fetches
+/// the blocks it does not know and their missing ancestors, and relays the
+/// valid ones.
+///
+/// Miners reach the node through [`Peer::transport`]. The threads of the
+/// peer run as long as the process.
+pub struct Peer {
+    shared: Arc<Shared>,
+    local_addr: SocketAddr,
+}
+
+impl Peer {
+    /// Listens on `listen`, such as `0.0.0.0:9000`, and connects to `peers`,
+    /// retrying the ones which cannot be reached.
+    pub fn start(node: Arc<Mutex<Node>>, listen: &str, peers: Vec<String>) -> io::Result<Self> {
+        let listener = TcpListener::bind(listen)?;
+        let local_addr = listener.local_addr()?;
+        let shared = Arc::new(Shared {
+            node,
+            connections: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(0),
+        });
+
+        let accepting = shared.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                accepting.add_connection(stream);
+            }
+        });
+
+        // Relays the blocks connected by the node, ours and our peers' ones
+        let events = shared.node.lock().unwrap().subscribe();
+        let relaying = shared.clone();
+        thread::spawn(move || {
+            for event in events {
+                if let StreamEvent::Block(_, block) = event {
+                    relaying.broadcast(&Message::Inv { hashes: vec![block.hash_block()] });
+                }
+            }
+        });
+
+        let dialing = shared.clone();
+        thread::spawn(move || dialing.dial(peers));
+
+        Ok(Peer { shared, local_addr })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Number of open connections to other peers.
+    pub fn peer_count(&self) -> usize {
+        self.shared.connections.lock().unwrap().len()
+    }
+
+    pub fn node(&self) -> &Arc<Mutex<Node>> {
+        &self.shared.node
+    }
+
+    /// Transport for a miner working on the chain of this peer.
+    pub fn transport(&self) -> LocalTransport {
+        LocalTransport::new(self.shared.node.clone())
+    }
+}
+
+impl Shared {
+    /// Keeps connections open to `peers`.
+    fn dial(self: Arc<Self>, peers: Vec<String>) {
+        let mut connected: HashMap<String, u64> = HashMap::new();
+        loop {
+            for peer in &peers {
+                if let Some(id) = connected.get(peer) {
+                    if self.connections.lock().unwrap().contains_key(id) {
+                        continue;
+                    }
+                }
+                let stream = peer
+                    .to_socket_addrs()
+                    .ok()
+                    .and_then(|mut addrs| addrs.next())
+                    .and_then(|addr| TcpStream::connect_timeout(&addr, WRITE_TIMEOUT).ok());
+                match stream {
+                    Some(stream) => {
+                        println!("Connected to peer {}", peer);
+                        if let Some(id) = self.add_connection(stream) {
+                            connected.insert(peer.clone(), id);
+                        }
+                    }
+                    None => println!("Cannot reach peer {}", peer),
+                }
+            }
+            thread::sleep(RECONNECT_INTERVAL);
+        }
+    }
+
+    /// Starts exchanging messages on `stream`, and returns the id of the
+    /// connection.
+    fn add_connection(self: &Arc<Self>, stream: TcpStream) -> Option<u64> {
+        let reader = stream.try_clone().ok()?;
+        stream.set_write_timeout(Some(WRITE_TIMEOUT)).ok()?;
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (queue, lines) = mpsc::sync_channel(MAX_QUEUED_MESSAGES);
+        self.connections.lock().unwrap().insert(id, queue);
+
+        let writing = self.clone();
+        thread::spawn(move || writing.write_messages(id, stream, lines));
+
+        // Lets the peer catch up with our chain
+        let best_tip = self.node.lock().unwrap().chain().best_tip().try_into().unwrap();
+        self.send(id, &Message::Inv { hashes: vec![best_tip] });
+
+        let shared = self.clone();
+        thread::spawn(move || {
+            let mut reader = BufReader::new(reader);
+            let mut line = Vec::new();
+            loop {
+                match read_line(&mut reader, &mut line, MAX_MESSAGE_LEN) {
+                    Ok(true) => {}
+                    Ok(false) => break,
+                    Err(e) => {
+                        println!("Dropping peer: {}", e);
+                        break;
+                    }
+                }
+                match serde_json::from_slice(&line) {
+                    Ok(message) => shared.handle(id, message),
+                    Err(e) => {
+                        println!("Invalid message from peer: {}", e);
+                        break;
+                    }
+                }
+            }
+            // Also stops the writing thread
+            shared.connections.lock().unwrap().remove(&id);
+        });
+        Some(id)
+    }
+
+    /// Writes the queued messages of a connection, until the connection is
+    /// dropped or fails. Then closes it, which stops the reading thread.
+    fn write_messages(&self, id: u64, mut stream: TcpStream, lines: Receiver<Arc<[u8]>>) {
+        for line in lines {
+            if stream.write_all(&line).is_err() {
+                break;
+            }
+        }
+        self.connections.lock().unwrap().remove(&id);
+        let _ = stream.shutdown(Shutdown::Both);
+    }
+
+    fn handle(&self, from: u64, message: Message) {
+        match message {
+            Message::Inv { hashes } => {
+                let node = self.node.lock().unwrap();
+                let wanted: Vec<[u8; 32]> = hashes
+                    .into_iter()
+                    .take(MAX_PAGE_BLOCKS)
+                    .filter(|hash| !knows(&node, hash))
+                    .collect();
+                drop(node);
+                if !wanted.is_empty() {
+                    self.send(from, &Message::GetBlocks { hashes: wanted });
+                }
+            }
+            Message::GetBlocks { hashes } => {
+                let node = self.node.lock().unwrap();
+                let blocks = hashes
+                    .iter()
+                    .take(MAX_PAGE_BLOCKS)
+                    .filter_map(|hash| node.get(hash).cloned())
+                    .collect();
+                drop(node);
+                for message in blocks_messages(blocks, MAX_MESSAGE_LEN) {
+                    self.send(from, &message);
+                }
+            }
+            Message::Blocks { blocks } => {
+                // Connected blocks are relayed by the node subscription,
+                // invalid ones are dropped
+                let mut missing = Vec::new();
+                let mut node = self.node.lock().unwrap();
+                for block in blocks {
+                    let parent: Option<[u8; 32]> = block.parent_hash.as_slice().try_into().ok();
+                    if let Ok(BlockStatus::Orphaned) = node.submit(block) {
+                        missing.extend(parent.filter(|parent| !knows(&node, parent)));
+                    }
+                }
+                drop(node);
+                // Walks back the chain of the peer until we know the blocks
+                if !missing.is_empty() {
+                    self.send(from, &Message::GetBlocks { hashes: missing });
+                }
+            }
+        }
+    }
+
+    fn send(&self, to: u64, message: &Message) {
+        let queue = self.connections.lock().unwrap().get(&to).cloned();
+        if let Some(queue) = queue {
+            self.enqueue(to, &queue, encode(message).into());
+        }
+    }
+
+    fn broadcast(&self, message: &Message) {
+        let line: Arc<[u8]> = encode(message).into();
+        let queues: Vec<_> = self
+            .connections
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, queue)| (*id, queue.clone()))
+            .collect();
+        for (id, queue) in queues {
+            self.enqueue(id, &queue, line.clone());
+        }
+    }
+
+    /// Queues a line for a connection, dropping the connection if its queue
+    /// is full.
+    fn enqueue(&self, id: u64, queue: &SyncSender<Arc<[u8]>>, line: Arc<[u8]>) {
+        if queue.try_send(line).is_err() {
+            self.connections.lock().unwrap().remove(&id);
+        }
+    }
+}
+
+fn encode(message: &Message) -> Vec<u8> {
+    let mut line = serde_json::to_vec(message).expect("messages serialize to JSON");
+    line.push(b'\n');
+    line
+}
+
+/// Reads the next line into `line`, without its newline. Returns false at
+/// the end of the stream, and fails on lines longer than `max_len`.
+fn read_line<R: BufRead>(reader: &mut R, line: &mut Vec<u8>, max_len: usize) -> io::Result<bool> {
+    line.clear();
+    if reader.by_ref().take(max_len as u64 + 1).read_until(b'\n', line)? == 0 {
+        return Ok(false);
+    }
+    if line.last() == Some(&b'\n') {
+        line.pop();
+    } else if line.len() > max_len {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "message too long"));
+    }
+    Ok(true)
+}
+
+/// Answers to `GetBlocks` holding `blocks`, split so that each message
+/// stays within `max_len`.
+fn blocks_messages(blocks: Vec<Block>, max_len: usize) -> Vec<Message> {
+    // Room for the fields of the message around its blocks
+    const ENVELOPE_LEN: usize = 64;
+    let mut messages = Vec::new();
+    let mut current = Vec::new();
+    let mut len = ENVELOPE_LEN;
+    for block in blocks {
+        // The block and the comma before it
+        let block_len = serde_json::to_vec(&block).expect("blocks serialize to JSON").len() + 1;
+        if !current.is_empty() && len + block_len > max_len {
+            messages.push(Message::Blocks {
+                blocks: std::mem::take(&mut current),
+            });
+            len = ENVELOPE_LEN;
+        }
+        len += block_len;
+        current.push(block);
+    }
+    if !current.is_empty() || messages.is_empty() {
+        messages.push(Message::Blocks { blocks: current });
+    }
+    messages
+}
+
+/// Whether the block is connected, or waiting for its parent.
+fn knows(node: &Node, hash: &[u8; 32]) -> bool {
+    node.get(hash).is_some() || node.chain().is_orphan(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_encoding() {
+        let message = Message::GetBlocks { hashes: vec![[1; 32]] };
+        let line = encode(&message);
+        assert_eq!(line.last(), Some(&b'\n'));
+        assert!(line.starts_with(br#"{"type":"get_blocks","hashes":[[1,1,"#));
+        assert_eq!(serde_json::from_slice::<Message>(&line).unwrap(), message);
+    }
+
+    #[test]
+    fn test_read_line() {
+        let mut reader = "short\nexactly10\nlast".as_bytes();
+        let mut line = Vec::new();
+        assert!(read_line(&mut reader, &mut line, 10).unwrap());
+        assert_eq!(line, b"short");
+        assert!(read_line(&mut reader, &mut line, 10).unwrap());
+        assert_eq!(line, b"exactly10");
+        assert!(read_line(&mut reader, &mut line, 10).unwrap());
+        assert_eq!(line, b"last");
+        assert!(!read_line(&mut reader, &mut line, 10).unwrap());
+
+        // A line without end is not read past the limit
+        let mut reader = BufReader::new(io::repeat(b'x'));
+        assert!(read_line(&mut reader, &mut line, 10).is_err());
+        assert_eq!(line.len(), 11);
+    }
+
+    #[test]
+    fn test_blocks_messages() {
+        let blocks: Vec<Block> = (0..10)
+            .map(|nonce| Block::new(vec![1], "miner".to_string(), nonce, crate::block::DanceMove::A))
+            .collect();
+        let one = encode(&Message::Blocks {
+            blocks: blocks[..1].to_vec(),
+        })
+        .len();
+
+        let messages = blocks_messages(blocks.clone(), 3 * one);
+        assert!(messages.len() > 1);
+        let mut received = Vec::new();
+        for message in messages {
+            assert!(encode(&message).len() <= 3 * one);
+            let Message::Blocks { blocks } = message else {
+                panic!("not a blocks message");
+            };
+            received.extend(blocks);
+        }
+        assert_eq!(received, blocks);
+        assert_eq!(blocks_messages(Vec::new(), one), vec![Message::Blocks { blocks: Vec::new() }]);
+    }
+}
@@ -0,0 +1,157 @@
+use crate::block::Block;
+use crate::block::CancelHandle;
+use crate::network::TransportError;
+use rand::thread_rng;
+use serde::{Deserialize, Serialize};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use std::time::Instant;
+
+/// How often a pool miner asks for new work, to learn about new best tips.
+pub const WORK_REFRESH: Duration = Duration::from_secs(1);
+
+/// Answer of `GET /pool/work`: the block the pool mines, signed by the pool
+/// so that it earns the reward. Only the nonce is left to find.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PoolWork {
+    pub block: Block,
+    /// Leading zero bits of the hash of a share, fewer than the difficulty
+    /// of the block.
+    pub share_difficulty: u32,
+}
+
+/// Body of `POST /pool/share`: the work of the pool with a nonce meeting
+/// the share difficulty.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Share {
+    /// Public key of the miner, credited with the share.
+    pub miner: Vec<u8>,
+    pub block: Block,
+}
+
+/// Answer of `POST /pool/share` to a share the pool counted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ShareStatus {
+    Accepted,
+    /// The share also meets the difficulty of the block, which the pool
+    /// added to the chain, ending the round.
+    BlockFound,
+}
+
+/// How a pool miner talks to its pool.
+pub trait PoolTransport {
+    /// The block the pool mines now.
+    fn work(&mut self) -> Result<PoolWork, TransportError>;
+
+    /// Sends a share we found.
+    fn submit_share(&mut self, share: &Share) -> Result<ShareStatus, TransportError>;
+
+    /// How long to wait before retrying after the pool was unavailable.
+    fn backoff(&self) -> Duration {
+        Duration::ZERO
+    }
+}
+
+/// Work being solved in the background.
+struct ShareJob {
+    work: PoolWork,
+    cancel: CancelHandle,
+    /// Returns the share found, or None if cancelled.
+    handle: JoinHandle<Option<Block>>,
+}
+
+impl ShareJob {
+    fn start(work: PoolWork, threads: usize) -> Self {
+        let cancel = CancelHandle::new();
+        let job_cancel = cancel.clone();
+        let mut block = work.block.clone();
+        let share_difficulty = work.share_difficulty;
+        let handle = thread::spawn(move || {
+            block
+                .search_nonce(&mut thread_rng(), share_difficulty, None, threads, &job_cancel)
+                .map(|_| block)
+        });
+        ShareJob { work, cancel, handle }
+    }
+}
+
+/// Mines shares of the work of the pool reached through `transport` for
+/// the miner with the public key `miner`, until `max_shares` shares are
+/// accepted. Returns the number of shares accepted.
+pub fn mine_for_pool<T: PoolTransport>(miner: &[u8], threads: usize, max_shares: Option<u64>, mut transport: T) -> u64 {
+    let mut job: Option<ShareJob> = None;
+    let mut accepted = 0;
+
+    while max_shares.is_none_or(|max| accepted < max) {
+        let work = match transport.work() {
+            Ok(work) => work,
+            Err(TransportError::Unavailable(e)) => {
+                println!("Cannot get work from the pool: {}", e);
+                thread::sleep(transport.backoff().max(WORK_REFRESH));
+                continue;
+            }
+            Err(e) => {
+                println!("Pool refused to give work: {}", e);
+                break;
+            }
+        };
+
+        // Stop working on a block the pool does not mine anymore
+        if job.as_ref().is_some_and(|job| job.work != work) {
+            println!("New work, restarting mining");
+            let stale = job.take().unwrap();
+            stale.cancel.cancel();
+            let _ = stale.handle.join();
+        }
+        let current = job.get_or_insert_with(|| ShareJob::start(work, threads));
+
+        // Until the next refresh, unless a share is found earlier
+        let refresh = Instant::now() + WORK_REFRESH;
+        while !current.handle.is_finished() && Instant::now() < refresh {
+            thread::sleep(Duration::from_millis(10));
+        }
+        if !current.handle.is_finished() {
+            continue;
+        }
+        let finished = job.take().unwrap();
+        if let Some(block) = finished.handle.join().expect("Mining thread panicked") {
+            let share = Share {
+                miner: miner.to_vec(),
+                block,
+            };
+            match transport.submit_share(&share) {
+                Ok(status) => {
+                    accepted += 1;
+                    if status == ShareStatus::BlockFound {
+                        println!("Found a block for the pool, hash: {:?}", share.block.hash_block());
+                    }
+                }
+                Err(e) => println!("Share refused: {}", e),
+            }
+            // Keep solving the same work for more shares
+            job = Some(ShareJob::start(finished.work, threads));
+        }
+    }
+
+    if let Some(job) = job {
+        job.cancel.cancel();
+        let _ = job.handle.join();
+    }
+    accepted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_share_status_json() {
+        assert_eq!(serde_json::to_string(&ShareStatus::BlockFound).unwrap(), r#"{"status":"block_found"}"#);
+        assert_eq!(
+            serde_json::from_str::<ShareStatus>(r#"{"status":"accepted"}"#).unwrap(),
+            ShareStatus::Accepted
+        );
+    }
+}
@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 pub trait Parenting {
     #[allow(dead_code)]
     fn is_parent(&self, parent_id: &[u8]) -> bool;
@@ -110,6 +112,117 @@ impl<T: Default + Parenting> TreeNode<T> {
     }
 }
 
+/// A block store indexed by hash.
+///
+/// Unlike [`TreeNode`], which has to be searched depth-first to find the parent
+/// of a new value, every node here is keyed by its (cached) hash and keeps the
+/// hashes of its children. Inserting a value and looking up its parent or its
+/// children are therefore O(1).
+#[derive(Debug)]
+pub struct BlockTree<T: Parenting> {
+    /// Hash of the root value.
+    root: Vec<u8>,
+    nodes: HashMap<Vec<u8>, BlockNode<T>>,
+}
+
+#[derive(Debug)]
+struct BlockNode<T> {
+    value: T,
+    /// Hashes of the children of this node, in insertion order.
+    children: Vec<Vec<u8>>,
+}
+
+impl<T: Parenting> BlockTree<T> {
+    /// Create a new store containing only `root`
+    pub fn new(root: T) -> Self {
+        let hash = root.hash();
+        let mut nodes = HashMap::new();
+        nodes.insert(
+            hash.clone(),
+            BlockNode {
+                value: root,
+                children: Vec::new(),
+            },
+        );
+        BlockTree { root: hash, nodes }
+    }
+
+    /// Insert a value under its parent.
+    /// Returns false if the parent is unknown or the value is already stored.
+    pub fn insert(&mut self, value: T) -> bool {
+        let hash = value.hash();
+        self.insert_with_hash(hash, value)
+    }
+
+    /// Same as [`BlockTree::insert`], for callers that already computed the
+    /// hash of `value`.
+    pub fn insert_with_hash(&mut self, hash: Vec<u8>, value: T) -> bool {
+        if self.nodes.contains_key(&hash) {
+            return false;
+        }
+        match self.nodes.get_mut(value.parent_hash()) {
+            Some(parent) => parent.children.push(hash.clone()),
+            None => return false,
+        }
+        self.nodes.insert(
+            hash,
+            BlockNode {
+                value,
+                children: Vec::new(),
+            },
+        );
+        true
+    }
+
+    /// Check whether a value with the given hash is stored
+    pub fn contains(&self, hash: &[u8]) -> bool {
+        self.nodes.contains_key(hash)
+    }
+
+    /// Get the value with the given hash
+    pub fn get(&self, hash: &[u8]) -> Option<&T> {
+        self.nodes.get(hash).map(|node| &node.value)
+    }
+
+    /// Get the parent of the value with the given hash.
+    /// Returns None for the root and for unknown hashes.
+    pub fn parent(&self, hash: &[u8]) -> Option<&T> {
+        if hash == self.root.as_slice() {
+            return None;
+        }
+        let node = self.nodes.get(hash)?;
+        self.get(node.value.parent_hash())
+    }
+
+    /// Get the hashes of the children of the value with the given hash
+    pub fn children(&self, hash: &[u8]) -> &[Vec<u8>] {
+        self.nodes
+            .get(hash)
+            .map(|node| node.children.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Get the root value
+    pub fn root(&self) -> &T {
+        &self.nodes[&self.root].value
+    }
+
+    /// Get the hash of the root value
+    pub fn root_hash(&self) -> &[u8] {
+        &self.root
+    }
+
+    /// Number of values stored, root included
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// A store always holds its root, so it is never empty
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -166,4 +279,24 @@ mod tests {
         assert_eq!(root.children().len(), 1);
         assert_eq!(root.children()[0].value(), &Data::new(3, [42]));
     }
+
+    #[test]
+    fn test_block_tree_operations() {
+        let mut tree = BlockTree::new(Data::new(42, [0]));
+        assert!(tree.insert(Data::new(2, [42])));
+        assert!(tree.insert(Data::new(3, [42])));
+        assert!(tree.insert(Data::new(4, [2])));
+
+        // Unknown parent and duplicates are rejected
+        assert!(!tree.insert(Data::new(7, [9])));
+        assert!(!tree.insert(Data::new(3, [42])));
+
+        assert_eq!(tree.len(), 4);
+        assert_eq!(tree.root(), &Data::new(42, [0]));
+        assert_eq!(tree.children(&[42]), &[vec![2], vec![3]]);
+        assert_eq!(tree.parent(&[4]), Some(&Data::new(2, [42])));
+        assert_eq!(tree.parent(&[42]), None);
+        assert!(tree.contains(&[4]));
+        assert!(!tree.contains(&[7]));
+    }
 }
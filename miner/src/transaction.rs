@@ -0,0 +1,124 @@
+use crate::error::TransactionError;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// A transfer between two accounts, identified by their Ed25519 public keys.
+#[derive(Clone, Default, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+pub struct Transaction {
+    /// Public key of the account debited.
+    pub from: Vec<u8>,
+    /// Public key of the account credited.
+    pub to: Vec<u8>,
+    /// Amount credited to `to`.
+    pub amount: u64,
+    /// Amount paid to the miner of the block including the transaction.
+    pub fee: u64,
+    /// Number of transactions `from` sent before this one, so that a
+    /// transaction cannot be replayed.
+    pub nonce: u64,
+    /// Signature by `from` of every other field.
+    pub signature: Vec<u8>,
+}
+
+impl Transaction {
+    /// Creates a transfer from the account of `key`, signed for the network
+    /// `network_id` so that it cannot be replayed on another network.
+    pub fn transfer(key: &SigningKey, to: Vec<u8>, amount: u64, fee: u64, nonce: u64, network_id: &str) -> Self {
+        let mut tx = Transaction {
+            from: key.verifying_key().to_bytes().to_vec(),
+            to,
+            amount,
+            fee,
+            nonce,
+            signature: Vec::new(),
+        };
+        tx.signature = key.sign(&tx.signing_payload(network_id)).to_bytes().to_vec();
+        tx
+    }
+
+    /// Bytes covered by the signature: the network and every field but the
+    /// signature itself.
+    fn signing_payload(&self, network_id: &str) -> Vec<u8> {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&(network_id.len() as u64).to_be_bytes());
+        payload.extend_from_slice(network_id.as_bytes());
+        payload.extend_from_slice(&(self.from.len() as u64).to_be_bytes());
+        payload.extend_from_slice(&self.from);
+        payload.extend_from_slice(&(self.to.len() as u64).to_be_bytes());
+        payload.extend_from_slice(&self.to);
+        payload.extend_from_slice(&self.amount.to_be_bytes());
+        payload.extend_from_slice(&self.fee.to_be_bytes());
+        payload.extend_from_slice(&self.nonce.to_be_bytes());
+        payload
+    }
+
+    /// Checks that the transaction is signed by `from` for `network_id`.
+    pub fn verify_signature(&self, network_id: &str) -> Result<(), TransactionError> {
+        let from: [u8; 32] = self
+            .from
+            .as_slice()
+            .try_into()
+            .map_err(|_| TransactionError::InvalidPublicKey)?;
+        let from = VerifyingKey::from_bytes(&from).map_err(|_| TransactionError::InvalidPublicKey)?;
+        let signature = Signature::from_slice(&self.signature).map_err(|_| TransactionError::InvalidSignature)?;
+        from.verify(&self.signing_payload(network_id), &signature)
+            .map_err(|_| TransactionError::InvalidSignature)
+    }
+
+    /// Hash identifying the transaction, covering every field.
+    pub fn hash(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update((self.from.len() as u64).to_be_bytes());
+        hasher.update(&self.from);
+        hasher.update((self.to.len() as u64).to_be_bytes());
+        hasher.update(&self.to);
+        hasher.update(self.amount.to_be_bytes());
+        hasher.update(self.fee.to_be_bytes());
+        hasher.update(self.nonce.to_be_bytes());
+        hasher.update((self.signature.len() as u64).to_be_bytes());
+        hasher.update(&self.signature);
+        hasher.finalize().into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash() {
+        let tx = Transaction {
+            from: vec![1; 32],
+            to: vec![2; 32],
+            amount: 10,
+            fee: 1,
+            nonce: 0,
+            signature: vec![3; 64],
+        };
+        assert_eq!(tx.hash(), tx.clone().hash());
+
+        // Every field is covered, length prefixed
+        let mut other = tx.clone();
+        other.fee = 2;
+        assert_ne!(other.hash(), tx.hash());
+        let mut other = tx.clone();
+        other.from = vec![1; 31];
+        other.to = [vec![1], vec![2; 32]].concat();
+        assert_ne!(other.hash(), tx.hash());
+    }
+
+    #[test]
+    fn test_signature() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let tx = Transaction::transfer(&key, vec![2; 32], 10, 1, 0, "testnet");
+        assert_eq!(tx.verify_signature("testnet"), Ok(()));
+        assert_eq!(tx.verify_signature("othernet"), Err(TransactionError::InvalidSignature));
+
+        let mut other = tx.clone();
+        other.amount = 11;
+        assert_eq!(other.verify_signature("testnet"), Err(TransactionError::InvalidSignature));
+        other.from = vec![1; 31];
+        assert_eq!(other.verify_signature("testnet"), Err(TransactionError::InvalidPublicKey));
+    }
+}
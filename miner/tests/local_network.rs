@@ -0,0 +1,101 @@
+//! Runs several miners against a server node in the same process.
+
+use ed25519_dalek::SigningKey;
+use miner::block::{Block, DanceMove};
+use miner::consensus::DefaultRules;
+use miner::ledger;
+use miner::mining::{self, MinerConfig};
+use miner::network::LocalTransport;
+use miner::node::Node;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+const DIFFICULTY: u32 = 8;
+
+fn genesis() -> Block {
+    let mut genesis = Block::new(Vec::new(), "Genesis".to_string(), 0, DanceMove::Y);
+    genesis.solve_block(&mut rand::thread_rng(), DIFFICULTY, None);
+    genesis
+}
+
+fn miner(genesis: &Block, index: u8, max_blocks: u64) -> MinerConfig {
+    MinerConfig {
+        name: format!("miner{}", index),
+        key: SigningKey::from_bytes(&[index; 32]),
+        threads: 1,
+        genesis: genesis.clone(),
+        max_blocks: Some(max_blocks),
+        rules: Arc::new(DefaultRules),
+    }
+}
+
+#[test]
+fn test_miners_share_a_chain() {
+    let genesis = genesis();
+    let node = Arc::new(Mutex::new(Node::new(genesis.clone(), DIFFICULTY)));
+
+    let miners: Vec<_> = (1..=3)
+        .map(|index| {
+            let config = miner(&genesis, index, 4);
+            let transport = LocalTransport::new(node.clone());
+            thread::spawn(move || mining::mine(&config, transport))
+        })
+        .collect();
+    let chains: Vec<_> = miners.into_iter().map(|miner| miner.join().unwrap()).collect();
+
+    let node = node.lock().unwrap();
+    // Every mined block was accepted by the server, on top of its genesis
+    assert_eq!(node.len(), 1 + 3 * 4);
+    assert_eq!(node.blocks().next(), Some(&genesis));
+    for index in 1..=3 {
+        let name = format!("miner{}", index);
+        assert_eq!(node.blocks().filter(|block| block.miner == name).count(), 4);
+    }
+
+    // Miners only ever extended chains the server knows
+    for chain in &chains {
+        assert_eq!(chain.genesis(), &genesis);
+        let tip: [u8; 32] = chain.best_tip().try_into().unwrap();
+        assert!(node.get(&tip).is_some());
+        assert!(node.chain().chain_work(&tip) <= node.chain().chain_work(node.chain().best_tip()));
+    }
+}
+
+#[test]
+fn test_miners_follow_the_best_chain() {
+    let genesis = genesis();
+    let node = Arc::new(Mutex::new(Node::new(genesis.clone(), DIFFICULTY)));
+
+    // A first miner builds a chain, which a late miner must extend
+    mining::mine(&miner(&genesis, 1, 5), LocalTransport::new(node.clone()));
+    let tip = node.lock().unwrap().chain().best_tip().to_vec();
+    let chain = mining::mine(&miner(&genesis, 2, 1), LocalTransport::new(node.clone()));
+
+    let node = node.lock().unwrap();
+    assert_eq!(node.len(), 1 + 5 + 1);
+    let last = chain.get(chain.best_tip()).unwrap();
+    assert_eq!(last.miner, "miner2");
+    assert_eq!(last.parent_hash, tip);
+    assert_eq!(node.chain().best_tip(), chain.best_tip());
+}
+
+#[test]
+fn test_template_miner() {
+    let genesis = genesis();
+    let node = Arc::new(Mutex::new(Node::new(genesis.clone(), DIFFICULTY)));
+
+    // Without a chain, the miner extends the best tip of the server
+    let config = miner(&genesis, 1, 3);
+    assert_eq!(mining::mine_from_templates(&config, LocalTransport::new(node.clone())), 3);
+    {
+        let node = node.lock().unwrap();
+        assert_eq!(node.chain().best_chain().len(), 1 + 3);
+        let miner_key = config.key.verifying_key().to_bytes();
+        assert_eq!(node.chain().ledger().account(&miner_key).balance, 3 * ledger::block_reward(1));
+    }
+
+    // The server tells a miner whose name it refuses
+    let mut config = miner(&genesis, 2, 1);
+    config.name = "changemeyoufool".to_string();
+    assert_eq!(mining::mine_from_templates(&config, LocalTransport::new(node.clone())), 0);
+}
@@ -0,0 +1,87 @@
+//! Runs several peers of a gossip network on localhost.
+
+use ed25519_dalek::SigningKey;
+use miner::block::{Block, DanceMove};
+use miner::consensus::DefaultRules;
+use miner::mining::{self, MinerConfig};
+use miner::node::Node;
+use miner::p2p::Peer;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const DIFFICULTY: u32 = 8;
+
+fn genesis() -> Block {
+    let mut genesis = Block::new(Vec::new(), "Genesis".to_string(), 0, DanceMove::Y);
+    genesis.solve_block(&mut rand::thread_rng(), DIFFICULTY, None);
+    genesis
+}
+
+fn miner(genesis: &Block, index: u8, max_blocks: u64) -> MinerConfig {
+    MinerConfig {
+        name: format!("miner{}", index),
+        key: SigningKey::from_bytes(&[index; 32]),
+        threads: 1,
+        genesis: genesis.clone(),
+        max_blocks: Some(max_blocks),
+        rules: Arc::new(DefaultRules),
+    }
+}
+
+fn peer(genesis: &Block, peers: &[&Peer]) -> Peer {
+    let node = Arc::new(Mutex::new(Node::new(genesis.clone(), 0)));
+    let peers = peers.iter().map(|peer| peer.local_addr().to_string()).collect();
+    Peer::start(node, "127.0.0.1:0", peers).unwrap()
+}
+
+/// Waits until every peer has the best tip of the first one, and returns it.
+fn wait_for_agreement(peers: &[&Peer], len: usize) -> Vec<u8> {
+    let deadline = Instant::now() + Duration::from_secs(30);
+    loop {
+        let tips: Vec<(usize, Vec<u8>)> = peers
+            .iter()
+            .map(|peer| {
+                let node = peer.node().lock().unwrap();
+                (node.len(), node.chain().best_tip().to_vec())
+            })
+            .collect();
+        if tips.iter().all(|tip| *tip == tips[0]) && tips[0].0 == len {
+            return tips[0].1.clone();
+        }
+        assert!(Instant::now() < deadline, "peers did not agree: {:?}", tips);
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn test_blocks_spread_through_the_network() {
+    let genesis = genesis();
+    // a - b - c: blocks of `a` reach `c` through `b`
+    let a = peer(&genesis, &[]);
+    let b = peer(&genesis, &[&a]);
+    let c = peer(&genesis, &[&b]);
+
+    mining::mine(&miner(&genesis, 1, 3), a.transport());
+    wait_for_agreement(&[&a, &b, &c], 1 + 3);
+
+    // and the other way around
+    let chain = mining::mine(&miner(&genesis, 2, 2), c.transport());
+    let tip = wait_for_agreement(&[&a, &b, &c], 1 + 3 + 2);
+    assert_eq!(chain.best_tip(), tip);
+    assert_eq!(chain.get(&tip).unwrap().miner, "miner2");
+}
+
+#[test]
+fn test_late_peer_fetches_missing_ancestors() {
+    let genesis = genesis();
+    let a = peer(&genesis, &[]);
+    mining::mine(&miner(&genesis, 1, 5), a.transport());
+
+    // The new peer only hears of the best tip, and walks back to the genesis
+    let b = peer(&genesis, &[&a]);
+    let tip = wait_for_agreement(&[&a, &b], 1 + 5);
+    assert_eq!(b.node().lock().unwrap().chain().best_chain().len(), 1 + 5);
+    assert_eq!(tip, a.node().lock().unwrap().chain().best_tip());
+    assert_eq!(a.peer_count(), 1);
+}
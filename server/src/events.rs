@@ -0,0 +1,130 @@
+use miner::network::StreamEvent;
+use std::io;
+use std::io::Read;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+/// A ping is sent when no block was accepted for this long, so that clients
+/// notice dead connections, and we notice clients which went away.
+const KEEPALIVE: Duration = Duration::from_secs(15);
+/// The HTTP server buffers streamed bodies in chunks of this size, and sends
+/// a full chunk once more data comes. Events are padded so that they end one
+/// byte into a chunk, which is the start of the next event.
+const CHUNK_SIZE: usize = 8192;
+
+/// Pads `event` so that it is sent right away. Every event starts with a
+/// comment line, which clients ignore, and is followed by the ':' starting
+/// the comment of the next one. Only that byte stays in the buffer of the
+/// HTTP server, the stream starting with an extra one.
+fn encode(event: &str) -> Vec<u8> {
+    let padding = (CHUNK_SIZE - (event.len() + 2) % CHUNK_SIZE) % CHUNK_SIZE;
+    let mut bytes = vec![b' '; padding];
+    bytes.push(b'\n');
+    bytes.extend_from_slice(event.as_bytes());
+    bytes.push(b':');
+    bytes
+}
+
+fn ping() -> Vec<u8> {
+    encode("event: ping\ndata:\n\n")
+}
+
+/// Body of a `GET /events` response: the accepted blocks as server-sent
+/// events,
+///
+/// ```text
+/// id: <cursor after the block>
+/// event: block
+/// data: <JSON block>
+/// ```
+///
+/// The id is the cursor of `GET /blocks?since=<cursor>` following the block.
+/// A `ping` event is sent first, then every `KEEPALIVE` without blocks. The
+/// stream ends when the node stops sending blocks.
+pub struct EventStream {
+    events: Receiver<StreamEvent>,
+    /// Event being sent, and how much of it was read.
+    event: Vec<u8>,
+    pos: usize,
+}
+
+impl EventStream {
+    pub fn new(events: Receiver<StreamEvent>) -> Self {
+        EventStream {
+            events,
+            // Also sends the response headers right away
+            event: [&b":"[..], &ping()].concat(),
+            pos: 0,
+        }
+    }
+
+    pub fn into_response(self) -> rouille::Response {
+        rouille::Response {
+            status_code: 200,
+            headers: vec![
+                ("Content-Type".into(), "text/event-stream".into()),
+                ("Cache-Control".into(), "no-cache".into()),
+            ],
+            data: rouille::ResponseBody::from_reader(self),
+            upgrade: None,
+        }
+    }
+}
+
+impl Read for EventStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.event.len() {
+            self.event = match self.events.recv_timeout(KEEPALIVE) {
+                Ok(StreamEvent::Block(cursor, block)) => {
+                    let data = serde_json::to_string(&block)?;
+                    encode(&format!("id: {}\nevent: block\ndata: {}\n\n", cursor, data))
+                }
+                Ok(StreamEvent::Ping) | Err(RecvTimeoutError::Timeout) => ping(),
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            };
+            self.pos = 0;
+        }
+        let len = (&self.event[self.pos..]).read(buf)?;
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use miner::block::{Block, DanceMove};
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_stream() {
+        let (tx, rx) = mpsc::channel();
+        let mut stream = EventStream::new(rx);
+        let block = Block::new(vec![1, 2, 3], "miner".to_string(), 4, DanceMove::C);
+        tx.send(StreamEvent::Block(7, Box::new(block.clone()))).unwrap();
+        drop(tx);
+
+        let mut body = String::new();
+        stream.read_to_string(&mut body).unwrap();
+        assert_eq!(body.len() % CHUNK_SIZE, 1);
+        // A ping, then the block
+        let (ping, event) = body.split_at(CHUNK_SIZE);
+        assert!(ping.starts_with(": "));
+        assert!(ping.ends_with("\nevent: ping\ndata:\n\n"));
+        let data = serde_json::to_string(&block).unwrap();
+        assert!(event.ends_with(&format!("\nid: 7\nevent: block\ndata: {}\n\n:", data)));
+        assert!(event.trim_start_matches([':', ' ']).starts_with("\nid: 7"));
+    }
+
+    #[test]
+    fn test_encode() {
+        for len in [0, 1, CHUNK_SIZE - 2, CHUNK_SIZE - 1, CHUNK_SIZE, 3 * CHUNK_SIZE + 5] {
+            let event = "x".repeat(len);
+            let bytes = encode(&event);
+            assert_eq!(bytes.len() % CHUNK_SIZE, 0);
+            assert!(bytes[..bytes.len() - len - 2].iter().all(|&b| b == b' '));
+            assert_eq!(bytes[bytes.len() - len - 2], b'\n');
+            assert!(bytes.ends_with(format!("{}:", event).as_bytes()));
+        }
+    }
+}
@@ -0,0 +1,335 @@
+pub mod events;
+pub mod mempool;
+pub mod pool;
+pub mod storage;
+
+use miner::block::Block;
+use miner::block::MAX_BLOCK_TRANSACTIONS;
+use miner::blockchain::BlockStatus;
+use miner::blockchain::Blockchain;
+use miner::chainspec::ChainSpec;
+use miner::consensus::ConsensusRules;
+use miner::error::BlockValidationError;
+use miner::ledger::Account;
+use mempool::Mempool;
+use mempool::MempoolError;
+use miner::network::BlockPage;
+use miner::network::BlockTemplate;
+use miner::network::StreamEvent;
+use miner::node::Node;
+use miner::transaction::Transaction;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use storage::BlockLog;
+
+/// Why [`BlockServer::submit_block`] refused a block.
+#[derive(Debug)]
+pub enum SubmitError {
+    /// The block breaks the rules of the chain.
+    Invalid(BlockValidationError),
+    /// The block is valid but could not be stored, so it was not accepted.
+    Storage(io::Error),
+}
+
+impl From<BlockValidationError> for SubmitError {
+    fn from(e: BlockValidationError) -> Self {
+        SubmitError::Invalid(e)
+    }
+}
+
+impl fmt::Display for SubmitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SubmitError::Invalid(e) => write!(f, "Invalid block: {}", e),
+            SubmitError::Storage(e) => write!(f, "Cannot store block: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SubmitError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SubmitError::Invalid(e) => Some(e),
+            SubmitError::Storage(e) => Some(e),
+        }
+    }
+}
+
+/// A block server without its HTTP frontend: the accepted blocks, the rules
+/// they are accepted with, their storage, and the transactions waiting to be
+/// mined. Every accepted block is appended to the block log, which is
+/// replayed when opening the server. Pending transactions are only kept in
+/// memory.
+pub struct BlockServer {
+    /// The blocks we accepted and the rules we accept them with.
+    node: Node,
+    /// Accepted blocks, genesis excluded, on disk.
+    log: BlockLog,
+    /// Transactions that can be applied after the best chain.
+    mempool: Mempool,
+}
+
+impl BlockServer {
+    /// Opens the server of the network described by `spec`, storing the
+    /// blocks it accepts in `data_dir`. The blocks stored there are accepted
+    /// again.
+    pub fn open(spec: ChainSpec, data_dir: &Path) -> io::Result<Self> {
+        let genesis = spec.genesis.clone();
+        let min_difficulty = spec.params.min_difficulty;
+        Self::open_with_rules(genesis, min_difficulty, data_dir, Arc::new(spec))
+    }
+
+    /// Opens the server of the chain starting at `genesis` and following the
+    /// given rules, accepting blocks of at least `min_difficulty`.
+    pub fn open_with_rules(
+        genesis: Block,
+        min_difficulty: u32,
+        data_dir: &Path,
+        rules: Arc<dyn ConsensusRules>,
+    ) -> io::Result<Self> {
+        let (log, stored) = BlockLog::open(data_dir)?;
+        let mut node = Node::with_rules(genesis, min_difficulty, rules);
+        // The log holds blocks in the order they were connected, parents first
+        let stored_count = stored.len();
+        let restored = node.restore(stored);
+        if restored != stored_count {
+            eprintln!(
+                "Warning: {} blocks of the log do not extend the genesis block",
+                stored_count - restored
+            );
+        }
+        Ok(BlockServer {
+            node,
+            log,
+            mempool: Mempool::default(),
+        })
+    }
+
+    /// Checks a block from a miner and adds it to the chain. A block whose
+    /// parent is unknown is parked until the parent shows up.
+    ///
+    /// Blocks are stored before being connected, so that the log holds every
+    /// block we serve: a block that cannot be stored is refused, and an
+    /// orphan it connected that cannot be stored is dropped.
+    pub fn submit_block(&mut self, block: Block) -> Result<BlockStatus, SubmitError> {
+        let previous_tip = self.node.chain().best_tip().to_vec();
+        let log = &mut self.log;
+        let status = self.node.submit_with(block, |block| {
+            log.append(block).map_err(|e| {
+                eprintln!("Cannot store block {}: {}", hex::encode(block.hash_block()), e);
+                SubmitError::Storage(e)
+            })
+        })?;
+        if let BlockStatus::Connected(_) = &status {
+            self.update_mempool(&previous_tip);
+        }
+        Ok(status)
+    }
+
+    /// Follows a change of the best chain, from `previous_tip`, in the
+    /// mempool: the transactions of the blocks that left the best chain are
+    /// pending again, the ones of the new blocks are not anymore.
+    fn update_mempool(&mut self, previous_tip: &[u8]) {
+        let chain = self.node.chain();
+        if chain.best_tip() == previous_tip {
+            return;
+        }
+        let disconnected = chain
+            .ancestors(previous_tip)
+            .take_while(|block| !chain.is_on_best_chain(&block.hash_block()))
+            .flat_map(|block| block.transactions.iter().cloned())
+            .collect();
+        self.mempool.update(chain.ledger(), disconnected);
+    }
+
+    /// Checks a transaction against the best chain and the pending
+    /// transactions, and adds it to the mempool.
+    pub fn submit_transaction(&mut self, tx: Transaction) -> Result<(), MempoolError> {
+        let chain = self.node.chain();
+        self.mempool.add(tx, chain.ledger(), chain.rules().network_id())
+    }
+
+    /// Template of the next block of the best chain for the miner named
+    /// `miner`, with the pending transactions paying the most fees. Fails if
+    /// the name is forbidden.
+    pub fn template(&self, miner: &str) -> Result<BlockTemplate, BlockValidationError> {
+        self.node.template(miner, self.mempool.select(MAX_BLOCK_TRANSACTIONS))
+    }
+
+    /// The transactions waiting to be mined.
+    pub fn mempool(&self) -> &Mempool {
+        &self.mempool
+    }
+
+    /// The accepted blocks, genesis included, in the order they were
+    /// accepted.
+    pub fn blocks(&self) -> impl Iterator<Item = &Block> {
+        self.node.blocks()
+    }
+
+    /// Number of accepted blocks, genesis included.
+    pub fn len(&self) -> usize {
+        self.node.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.node.is_empty()
+    }
+
+    pub fn get(&self, hash: &[u8; 32]) -> Option<&Block> {
+        self.node.get(hash)
+    }
+
+    /// Blocks accepted after the first `since` ones.
+    pub fn blocks_since(&self, since: u64) -> BlockPage {
+        self.node.page(since)
+    }
+
+    /// The tree of accepted blocks, for fork choice and chain queries.
+    pub fn chain(&self) -> &Blockchain {
+        self.node.chain()
+    }
+
+    /// The blocks of the best chain, from the genesis to the best tip.
+    pub fn best_chain(&self) -> Vec<Block> {
+        self.node.chain().best_chain()
+    }
+
+    /// State of the account of the given public key after the best chain.
+    pub fn account(&self, key: &[u8]) -> Account {
+        self.node.chain().ledger().account(key)
+    }
+
+    /// State of the account of the given public key once its pending
+    /// transactions are mined.
+    pub fn pending_account(&self, key: &[u8]) -> Account {
+        self.mempool.pending_account(self.node.chain().ledger(), key)
+    }
+
+    /// Returns a receiver of the blocks accepted from now on.
+    pub fn subscribe(&mut self) -> Receiver<StreamEvent> {
+        self.node.subscribe()
+    }
+}
+
+/// Loads the chain spec stored in `path`, or creates a new network, mining
+/// its genesis block at the given difficulty, and stores its spec there.
+pub fn load_or_create_chain_spec(path: &Path, network_id: &str, difficulty: u32) -> io::Result<ChainSpec> {
+    if path.exists() {
+        return ChainSpec::load(path);
+    }
+
+    println!("Mining a new genesis block at difficulty {}...", difficulty);
+    let spec = ChainSpec::create(network_id, difficulty);
+    spec.save(path)?;
+    Ok(spec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::SigningKey;
+    use miner::block::DanceMove;
+    use std::fs;
+
+    fn child(parent: &Block, nonce: u64) -> Block {
+        child_with_transactions(parent, nonce, Vec::new())
+    }
+
+    fn child_with_transactions(parent: &Block, nonce: u64, transactions: Vec<Transaction>) -> Block {
+        let mut block = Block::new(parent.hash_block().to_vec(), "miner".to_string(), nonce, DanceMove::M);
+        block.network_id = parent.network_id.clone();
+        block.timestamp = parent.timestamp + 1;
+        block.difficulty = parent.difficulty;
+        block.set_transactions(transactions);
+        block.sign(&SigningKey::from_bytes(&[7; 32]));
+        block.solve_block(&mut rand::thread_rng(), parent.difficulty, None);
+        block
+    }
+
+    #[test]
+    fn test_submit_and_reopen() {
+        let dir = std::env::temp_dir().join(format!("block-server-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let spec_file = dir.join("chain.json");
+        fs::create_dir_all(&dir).unwrap();
+        let spec = load_or_create_chain_spec(&spec_file, "testnet", 4).unwrap();
+        assert_eq!(load_or_create_chain_spec(&spec_file, "othernet", 8).unwrap(), spec);
+        let genesis = spec.genesis.clone();
+
+        let mut server = BlockServer::open(spec.clone(), &dir.join("data")).unwrap();
+        let a = child(&genesis, 1);
+        let b = child(&a, 2);
+        assert!(matches!(server.submit_block(b.clone()), Ok(BlockStatus::Orphaned)));
+        assert!(matches!(server.submit_block(a.clone()), Ok(BlockStatus::Connected(_))));
+        assert!(matches!(server.submit_block(a.clone()), Ok(BlockStatus::Duplicate)));
+        let mut invalid = child(&b, 3);
+        invalid.miner = "changemeyoufool".to_string();
+        assert!(matches!(
+            server.submit_block(invalid),
+            Err(SubmitError::Invalid(BlockValidationError::ForbiddenMinerName { .. }))
+        ));
+        let mut other_network = child(&b, 4);
+        other_network.network_id = "othernet".to_string();
+        assert!(matches!(
+            server.submit_block(other_network),
+            Err(SubmitError::Invalid(BlockValidationError::WrongNetwork { .. }))
+        ));
+        assert_eq!(server.best_chain(), vec![genesis.clone(), a.clone(), b.clone()]);
+        drop(server);
+
+        // Accepted blocks are replayed
+        let server = BlockServer::open(spec, &dir.join("data")).unwrap();
+        assert_eq!(server.blocks().cloned().collect::<Vec<_>>(), vec![genesis, a, b]);
+        assert_eq!(server.blocks_since(3).blocks, Vec::new());
+        let miner = SigningKey::from_bytes(&[7; 32]).verifying_key().to_bytes();
+        assert_eq!(server.account(&miner).balance, 2 * miner::ledger::block_reward(1));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_mempool_follows_best_chain() {
+        let dir = std::env::temp_dir().join(format!("block-server-mempool-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let spec = ChainSpec::create("testnet", 4);
+        let genesis = spec.genesis.clone();
+        let mut server = BlockServer::open(spec, &dir).unwrap();
+        let a = child(&genesis, 1);
+        server.submit_block(a.clone()).unwrap();
+
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let tx = Transaction::transfer(&key, vec![9; 32], 10, 1, 0, "testnet");
+        server.submit_transaction(tx.clone()).unwrap();
+        assert_eq!(server.submit_transaction(tx.clone()), Err(MempoolError::Duplicate));
+        assert_eq!(server.mempool().select(usize::MAX), vec![tx.clone()]);
+
+        // Mined, the transaction leaves the mempool
+        let b = child_with_transactions(&a, 2, vec![tx.clone()]);
+        server.submit_block(b.clone()).unwrap();
+        assert!(server.mempool().is_empty());
+        assert_eq!(server.account(&[9; 32]).balance, 10);
+
+        // A branch without it takes over: it is pending again
+        let mut tip = child(&a, 3);
+        server.submit_block(tip.clone()).unwrap();
+        while server.chain().best_tip() == b.hash_block() {
+            tip = child(&tip, 4);
+            server.submit_block(tip.clone()).unwrap();
+        }
+        assert_eq!(server.account(&[9; 32]).balance, 0);
+        assert_eq!(server.mempool().select(usize::MAX), vec![tx.clone()]);
+
+        // Templates extend the new best tip with the pending transaction
+        let template = server.template("miner").unwrap();
+        assert_eq!(template.parent_hash, server.chain().best_tip());
+        assert_eq!(template.transactions, vec![tx]);
+        assert!(matches!(
+            server.template("changemeyoufool"),
+            Err(BlockValidationError::ForbiddenMinerName { .. })
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -3,9 +3,25 @@ extern crate rouille;
 
 use clap::Parser;
 use miner::block::Block;
-use miner::block::BlockIdHasher;
+use miner::block::DEFAULT_NETWORK_ID;
 use miner::block::DIFFICULTY;
-use std::collections::HashMap;
+use miner::blockchain::BlockStatus;
+use miner::chainspec::DEFAULT_CHAIN_SPEC_FILE;
+use miner::keys;
+use miner::pool::Share;
+use miner::transaction::Transaction;
+use serde::Serialize;
+use server::events::EventStream;
+use server::load_or_create_chain_spec;
+use server::mempool::MempoolError;
+use server::pool::Pool;
+use server::pool::ShareError;
+use server::pool::DEFAULT_POOL_NAME;
+use server::pool::DEFAULT_SHARE_OFFSET;
+use server::BlockServer;
+use server::SubmitError;
+use std::fmt;
+use std::path::PathBuf;
 use std::sync::Mutex;
 
 #[derive(Parser)]
@@ -15,17 +31,76 @@ struct Args {
     address: Option<String>,
     #[arg(short, default_value_t = 8080)]
     port: u16,
+    /// JSON file holding the chain spec of the network. If it does not
+    /// exist, a new network is created and its spec stored there.
+    #[arg(short, long, default_value = DEFAULT_CHAIN_SPEC_FILE)]
+    chain: PathBuf,
+    /// Id of the network, when creating a new one.
+    #[arg(long, default_value = DEFAULT_NETWORK_ID)]
+    network_id: String,
+    /// Difficulty of the genesis block, and minimum difficulty of the chain,
+    /// when creating a new network.
     #[arg(short, default_value_t = DIFFICULTY)]
     difficulty: u32,
+    /// Directory where the accepted blocks are stored.
+    #[arg(long, default_value = "data")]
+    data_dir: PathBuf,
+    /// Key of the mining pool, created by `miner_cli keygen`. The pool is
+    /// only served when set.
+    #[arg(long)]
+    pool_key: Option<PathBuf>,
+    /// Miner name of the blocks of the pool.
+    #[arg(long, default_value = DEFAULT_POOL_NAME)]
+    pool_name: String,
+    /// Leading zero bits a share has fewer than a block.
+    #[arg(long, default_value_t = DEFAULT_SHARE_OFFSET)]
+    share_offset: u32,
 }
 
-// Our database of blocks. If it dies, it dies.
-type BlockHashMap<V> = HashMap<u64, V, BlockIdHasher>;
+/// Response to an invalid request: status 400 with the code and details of
+/// the error, and a message for humans.
+fn refusal<E: Serialize + fmt::Display>(err: &E) -> rouille::Response {
+    let mut body = serde_json::to_value(err).unwrap();
+    body["message"] = err.to_string().into();
+    rouille::Response::json(&body).with_status_code(400)
+}
 
 fn main() {
     let args = Args::parse();
     let address = args.address.unwrap_or("0.0.0.0".to_string());
-    let db = Mutex::new(BlockHashMap::<Block>::default());
+
+    let spec = match load_or_create_chain_spec(&args.chain, &args.network_id, args.difficulty) {
+        Ok(spec) => spec,
+        Err(e) => {
+            eprintln!("Cannot load the chain spec from {:?}: {}", args.chain, e);
+            return;
+        }
+    };
+    println!("Network: {}", spec.network_id);
+    println!("Genesis block hash: {:?}", spec.genesis.hash_block());
+    let server = match BlockServer::open(spec.clone(), &args.data_dir) {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("Cannot open the block log in {:?}: {}", args.data_dir, e);
+            return;
+        }
+    };
+    println!("Loaded {} blocks from {:?}", server.len() - 1, args.data_dir);
+    let server = Mutex::new(server);
+
+    let pool = match &args.pool_key {
+        Some(path) => match keys::load(path) {
+            Ok(key) => {
+                println!("Pool public key: {}", hex::encode(key.verifying_key().to_bytes()));
+                Some(Mutex::new(Pool::new(key, &args.pool_name, args.share_offset)))
+            }
+            Err(e) => {
+                eprintln!("Cannot load the pool key from {:?}: {}", path, e);
+                return;
+            }
+        },
+        None => None,
+    };
 
     println!("Now listening on {:?}:{:?}", address, args.port);
 
@@ -33,8 +108,32 @@ fn main() {
         rouille::log(request, std::io::stdout(), || {
             router!(request,
                 (GET) (/blocks) => {
-                    let db = db.lock().unwrap();
-                    rouille::Response::json(&db.values().cloned().collect::<Vec<Block>>())
+                    let server = server.lock().unwrap();
+                    // Without a cursor, every block
+                    let Some(since) = request.get_param("since") else {
+                        return rouille::Response::json(&server.blocks().collect::<Vec<&Block>>());
+                    };
+                    match since.parse() {
+                        Ok(since) => rouille::Response::json(&server.blocks_since(since)),
+                        Err(_) => rouille::Response::text("Invalid cursor").with_status_code(400),
+                    }
+                },
+
+                (GET) (/chain) => {
+                    rouille::Response::json(&spec)
+                },
+
+                (GET) (/accounts/{key: String}) => {
+                    // Balance and next nonce of a hex encoded public key
+                    match hex::decode(&key) {
+                        Ok(key) => rouille::Response::json(&server.lock().unwrap().account(&key)),
+                        Err(_) => rouille::Response::text("Invalid public key").with_status_code(400),
+                    }
+                },
+
+                (GET) (/events) => {
+                    // Streams the blocks accepted from now on
+                    EventStream::new(server.lock().unwrap().subscribe()).into_response()
                 },
 
                 (POST) (/postblock) => {
@@ -52,18 +151,110 @@ fn main() {
                         }
                     };
 
-                    let mut db = db.lock().unwrap();
-                    if db.contains_key(&block.nonce) {
-                        return rouille::Response::text("Block already exists").with_status_code(400);
+                    match server.lock().unwrap().submit_block(block) {
+                        Ok(BlockStatus::Connected(_)) => {
+                            rouille::Response::text("Block accepted").with_status_code(200)
+                        }
+                        Ok(BlockStatus::Orphaned) => {
+                            rouille::Response::text("Block parked: unknown parent").with_status_code(202)
+                        }
+                        Ok(BlockStatus::Duplicate) => {
+                            rouille::Response::text("Block already exists").with_status_code(400)
+                        }
+                        Err(SubmitError::Invalid(err)) => refusal(&err),
+                        Err(SubmitError::Storage(_)) => {
+                            rouille::Response::text("Cannot store block").with_status_code(500)
+                        }
+                    }
+                },
+
+                (GET) (/template) => {
+                    let Some(miner) = request.get_param("miner") else {
+                        return rouille::Response::text("Expected a miner name").with_status_code(400);
+                    };
+                    match server.lock().unwrap().template(&miner) {
+                        Ok(template) => rouille::Response::json(&template),
+                        Err(err) => refusal(&err),
+                    }
+                },
+
+                (GET) (/mempool) => {
+                    // Pending transactions, in the order miners should include them
+                    rouille::Response::json(&server.lock().unwrap().mempool().select(usize::MAX))
+                },
+
+                (POST) (/tx) => {
+                    if request.header("Content-Type") != Some("application/json") {
+                        return rouille::Response::text("Expected Content-Type: application/json")
+                            .with_status_code(400);
+                    }
+
+                    let tx: Transaction = match rouille::input::json_input(request) {
+                        Ok(tx) => tx,
+                        Err(e) => {
+                            eprintln!("JSON parse error: {:?}", e);
+                            return rouille::Response::text("Invalid JSON format")
+                                .with_status_code(400);
+                        }
+                    };
+
+                    match server.lock().unwrap().submit_transaction(tx) {
+                        Ok(()) => rouille::Response::text("Transaction accepted").with_status_code(200),
+                        Err(MempoolError::Invalid(err)) => refusal(&err),
+                        Err(MempoolError::Duplicate) => {
+                            rouille::Response::text("Transaction already pending").with_status_code(400)
+                        }
+                        Err(MempoolError::Full) => {
+                            rouille::Response::text("Mempool full, try a higher fee").with_status_code(503)
+                        }
                     }
-                    let difficulty = args.difficulty;
-                    if let Err(err) = block.is_block_valid(difficulty) {
-                        return rouille::Response::text(format!("Invalid block: {}", err)).with_status_code(400);
+                },
+
+                (GET) (/pool/work) => {
+                    // The pool is locked before the server
+                    let Some(pool) = &pool else {
+                        return rouille::Response::empty_404();
+                    };
+                    match pool.lock().unwrap().work(&server.lock().unwrap()) {
+                        Ok(work) => rouille::Response::json(&work),
+                        Err(err) => refusal(&err),
                     }
-                    
+                },
 
-                    db.insert(block.nonce, block);
-                    rouille::Response::text("Block accepted").with_status_code(200)
+                (POST) (/pool/share) => {
+                    let Some(pool) = &pool else {
+                        return rouille::Response::empty_404();
+                    };
+                    if request.header("Content-Type") != Some("application/json") {
+                        return rouille::Response::text("Expected Content-Type: application/json")
+                            .with_status_code(400);
+                    }
+
+                    let share: Share = match rouille::input::json_input(request) {
+                        Ok(share) => share,
+                        Err(e) => {
+                            eprintln!("JSON parse error: {:?}", e);
+                            return rouille::Response::text("Invalid JSON format")
+                                .with_status_code(400);
+                        }
+                    };
+                    match pool.lock().unwrap().submit_share(&mut server.lock().unwrap(), share) {
+                        Ok(status) => rouille::Response::json(&status),
+                        Err(ShareError::Block(SubmitError::Storage(_))) => {
+                            rouille::Response::text("Cannot store block").with_status_code(500)
+                        }
+                        Err(e) => rouille::Response::text(e.to_string()).with_status_code(400),
+                    }
+                },
+
+                (GET) (/pool/stats) => {
+                    match &pool {
+                        Some(pool) => {
+                            let stats = pool.lock().unwrap().stats(&server.lock().unwrap());
+                            rouille::Response::json(&stats)
+                        }
+                        None => rouille::Response::empty_404(),
+                    }
                 },
 
                 _ => rouille::Response::empty_404()
@@ -71,3 +262,4 @@ fn main() {
         })
     });
 }
+
@@ -0,0 +1,297 @@
+use miner::error::TransactionError;
+use miner::ledger::Account;
+use miner::ledger::Ledger;
+use miner::transaction::Transaction;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+
+/// Maximum number of pending transactions.
+pub const MAX_MEMPOOL_TRANSACTIONS: usize = 10_000;
+
+/// Why [`Mempool::add`] refused a transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MempoolError {
+    /// The transaction cannot be applied after the best chain and the
+    /// pending transactions of its sender.
+    Invalid(TransactionError),
+    /// The transaction is already pending.
+    Duplicate,
+    /// The mempool is full of transactions paying at least as much.
+    Full,
+}
+
+impl fmt::Display for MempoolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MempoolError::Invalid(e) => write!(f, "Invalid transaction: {}", e),
+            MempoolError::Duplicate => write!(f, "Transaction already pending"),
+            MempoolError::Full => write!(f, "Mempool full, try a higher fee"),
+        }
+    }
+}
+
+impl std::error::Error for MempoolError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MempoolError::Invalid(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Transactions waiting to be included in a block.
+///
+/// Every pending transaction can be applied after the best chain: the
+/// transactions of a sender are kept in nonce order, the first one using the
+/// nonce of the sender in the ledger, and the sender can pay for all of them.
+/// Transfers to a sender only count once they are mined.
+#[derive(Debug)]
+pub struct Mempool {
+    /// Pending transactions by sender, in nonce order.
+    by_sender: HashMap<Vec<u8>, Vec<Transaction>>,
+    /// Hashes of the pending transactions.
+    hashes: HashSet<[u8; 32]>,
+    capacity: usize,
+}
+
+impl Default for Mempool {
+    fn default() -> Self {
+        Self::new(MAX_MEMPOOL_TRANSACTIONS)
+    }
+}
+
+impl Mempool {
+    /// Creates a mempool holding at most `capacity` transactions.
+    pub fn new(capacity: usize) -> Self {
+        Mempool {
+            by_sender: HashMap::new(),
+            hashes: HashSet::new(),
+            capacity,
+        }
+    }
+
+    /// Number of pending transactions.
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    pub fn contains(&self, hash: &[u8; 32]) -> bool {
+        self.hashes.contains(hash)
+    }
+
+    /// Adds a transaction of the network `network_id`, checked against the
+    /// ledger of the best chain and the pending transactions of its sender.
+    ///
+    /// A full mempool makes room by dropping the last pending transaction of
+    /// the sender paying the lowest fee, when the new one pays more.
+    pub fn add(&mut self, tx: Transaction, ledger: &Ledger, network_id: &str) -> Result<(), MempoolError> {
+        tx.verify_signature(network_id).map_err(MempoolError::Invalid)?;
+        self.insert(tx, ledger)
+    }
+
+    /// Adds a transaction whose signature is checked
+    fn insert(&mut self, tx: Transaction, ledger: &Ledger) -> Result<(), MempoolError> {
+        let hash = tx.hash();
+        if self.hashes.contains(&hash) {
+            return Err(MempoolError::Duplicate);
+        }
+        self.pending_account(ledger, &tx.from)
+            .check_transaction(&tx)
+            .map_err(MempoolError::Invalid)?;
+        if self.len() >= self.capacity {
+            self.make_room(&tx)?;
+        }
+        self.hashes.insert(hash);
+        self.by_sender.entry(tx.from.clone()).or_default().push(tx);
+        Ok(())
+    }
+
+    /// Account of `key` once its pending transactions are applied after
+    /// `ledger`, whose nonce is the one of its next transaction.
+    pub fn pending_account(&self, ledger: &Ledger, key: &[u8]) -> Account {
+        let mut account = ledger.account(key);
+        for tx in self.by_sender.get(key).into_iter().flatten() {
+            account.balance = account.balance.saturating_sub(tx.amount + tx.fee);
+            account.nonce += 1;
+        }
+        account
+    }
+
+    /// Drops the cheapest transaction that can go to make room for `tx`.
+    /// Only the last transaction of a sender can go, so that the nonces of
+    /// the others stay contiguous, and not one of the sender of `tx`.
+    fn make_room(&mut self, tx: &Transaction) -> Result<(), MempoolError> {
+        let cheapest = self
+            .by_sender
+            .iter()
+            .filter(|(sender, _)| **sender != tx.from)
+            .filter_map(|(sender, pending)| Some((pending.last()?.fee, sender)))
+            .min();
+        let sender = match cheapest {
+            Some((fee, sender)) if fee < tx.fee => sender.clone(),
+            _ => return Err(MempoolError::Full),
+        };
+        let pending = self.by_sender.get_mut(&sender).unwrap();
+        let dropped = pending.pop().unwrap();
+        if pending.is_empty() {
+            self.by_sender.remove(&sender);
+        }
+        self.hashes.remove(&dropped.hash());
+        Ok(())
+    }
+
+    /// At most `max` pending transactions, by decreasing fee as long as the
+    /// transactions of each sender stay in nonce order, so that they can be
+    /// applied in this order.
+    pub fn select(&self, max: usize) -> Vec<Transaction> {
+        // The next transaction of each sender, ties broken by hash
+        let mut next: BinaryHeap<_> = self
+            .by_sender
+            .values()
+            .map(|pending| (pending[0].fee, Reverse(pending[0].hash()), pending, 0))
+            .collect();
+        let mut selected = Vec::new();
+        while selected.len() < max {
+            let Some((_, _, pending, index)) = next.pop() else {
+                break;
+            };
+            selected.push(pending[index].clone());
+            if let Some(tx) = pending.get(index + 1) {
+                next.push((tx.fee, Reverse(tx.hash()), pending, index + 1));
+            }
+        }
+        selected
+    }
+
+    /// Follows a change of the best chain, whose ledger is `ledger`. The
+    /// transactions of the blocks that left the best chain come back, then
+    /// the transactions that cannot be applied anymore, such as the ones the
+    /// new blocks include, are dropped.
+    pub fn update(&mut self, ledger: &Ledger, disconnected: Vec<Transaction>) {
+        let mut transactions: Vec<Transaction> = self
+            .by_sender
+            .drain()
+            .flat_map(|(_, pending)| pending)
+            .chain(disconnected)
+            .collect();
+        transactions.sort_by(|a, b| (&a.from, a.nonce).cmp(&(&b.from, b.nonce)));
+        self.hashes.clear();
+        // Pending transactions and the ones of valid blocks are signed
+        for tx in transactions {
+            let _ = self.insert(tx, ledger);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::SigningKey;
+    use miner::block::Block;
+    use miner::block::DanceMove;
+    use miner::block::DEFAULT_NETWORK_ID;
+
+    /// A ledger where each key was rewarded 100 by a block.
+    fn ledger(keys: &[&SigningKey]) -> Ledger {
+        let mut ledger = Ledger::default();
+        for (height, key) in keys.iter().enumerate() {
+            let mut block = Block::new(vec![1; 32], "miner".to_string(), 0, DanceMove::Y);
+            block.sign(key);
+            ledger.apply_block(vec![height as u8], &block, 100).unwrap();
+        }
+        ledger
+    }
+
+    fn transfer(key: &SigningKey, amount: u64, fee: u64, nonce: u64) -> Transaction {
+        Transaction::transfer(key, vec![9; 32], amount, fee, nonce, DEFAULT_NETWORK_ID)
+    }
+
+    #[test]
+    fn test_add() {
+        let alice = SigningKey::from_bytes(&[1; 32]);
+        let ledger = ledger(&[&alice]);
+        let mut mempool = Mempool::default();
+
+        // Pending transactions chain their nonces and share the balance
+        mempool.add(transfer(&alice, 50, 1, 0), &ledger, DEFAULT_NETWORK_ID).unwrap();
+        assert_eq!(
+            mempool.add(transfer(&alice, 50, 1, 0), &ledger, DEFAULT_NETWORK_ID),
+            Err(MempoolError::Duplicate)
+        );
+        assert_eq!(
+            mempool.add(transfer(&alice, 40, 1, 2), &ledger, DEFAULT_NETWORK_ID),
+            Err(MempoolError::Invalid(TransactionError::BadNonce { nonce: 2, expected: 1 }))
+        );
+        assert_eq!(
+            mempool.add(transfer(&alice, 49, 1, 1), &ledger, DEFAULT_NETWORK_ID),
+            Err(MempoolError::Invalid(TransactionError::InsufficientBalance {
+                balance: 49,
+                needed: 50
+            }))
+        );
+        assert_eq!(
+            mempool.add(transfer(&alice, 40, 1, 1), &ledger, "othernet"),
+            Err(MempoolError::Invalid(TransactionError::InvalidSignature))
+        );
+        mempool.add(transfer(&alice, 40, 1, 1), &ledger, DEFAULT_NETWORK_ID).unwrap();
+        assert_eq!(mempool.len(), 2);
+    }
+
+    #[test]
+    fn test_fee_priority() {
+        let (alice, bob, carol) = (
+            SigningKey::from_bytes(&[1; 32]),
+            SigningKey::from_bytes(&[2; 32]),
+            SigningKey::from_bytes(&[3; 32]),
+        );
+        let ledger = ledger(&[&alice, &bob, &carol]);
+        let mut mempool = Mempool::new(3);
+        mempool.add(transfer(&alice, 1, 1, 0), &ledger, DEFAULT_NETWORK_ID).unwrap();
+        mempool.add(transfer(&alice, 1, 9, 1), &ledger, DEFAULT_NETWORK_ID).unwrap();
+        mempool.add(transfer(&bob, 1, 5, 0), &ledger, DEFAULT_NETWORK_ID).unwrap();
+
+        // Alice's better paying transaction waits for her first one
+        let fees: Vec<u64> = mempool.select(usize::MAX).iter().map(|tx| tx.fee).collect();
+        assert_eq!(fees, vec![5, 1, 9]);
+        assert_eq!(mempool.select(1).len(), 1);
+
+        // When full, the cheapest last transaction of a sender makes room
+        assert_eq!(
+            mempool.add(transfer(&carol, 1, 5, 0), &ledger, DEFAULT_NETWORK_ID),
+            Err(MempoolError::Full)
+        );
+        mempool.add(transfer(&carol, 1, 6, 0), &ledger, DEFAULT_NETWORK_ID).unwrap();
+        assert!(!mempool.contains(&transfer(&bob, 1, 5, 0).hash()));
+        assert_eq!(mempool.len(), 3);
+    }
+
+    #[test]
+    fn test_update() {
+        let alice = SigningKey::from_bytes(&[1; 32]);
+        let mut ledger = ledger(&[&alice]);
+        let mut mempool = Mempool::default();
+        let (first, second) = (transfer(&alice, 10, 1, 0), transfer(&alice, 10, 1, 1));
+        mempool.add(first.clone(), &ledger, DEFAULT_NETWORK_ID).unwrap();
+        mempool.add(second.clone(), &ledger, DEFAULT_NETWORK_ID).unwrap();
+
+        // A block includes the first transaction
+        let mut block = Block::new(vec![1; 32], "miner".to_string(), 0, DanceMove::Y);
+        block.set_transactions(vec![first.clone()]);
+        block.sign(&SigningKey::from_bytes(&[4; 32]));
+        ledger.apply_block(vec![9], &block, 100).unwrap();
+        mempool.update(&ledger, Vec::new());
+        assert_eq!(mempool.select(usize::MAX), vec![second.clone()]);
+
+        // Then leaves the best chain
+        ledger.revert_block();
+        mempool.update(&ledger, block.transactions.clone());
+        assert_eq!(mempool.select(usize::MAX), vec![first, second]);
+    }
+}
@@ -0,0 +1,451 @@
+use crate::BlockServer;
+use crate::SubmitError;
+use ed25519_dalek::SigningKey;
+use miner::block::DanceMove;
+use miner::blockchain::BlockStatus;
+use miner::error::BlockValidationError;
+use miner::pool::PoolWork;
+use miner::pool::Share;
+use miner::pool::ShareStatus;
+use miner::transaction::Transaction;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+
+/// Name of the miner of the blocks of the pool.
+pub const DEFAULT_POOL_NAME: &str = "pool";
+/// A share has this many leading zero bits fewer than a block, so that
+/// miners find 16 shares per block on average.
+pub const DEFAULT_SHARE_OFFSET: u32 = 4;
+
+/// Why [`Pool::submit_share`] refused a share.
+#[derive(Debug)]
+pub enum ShareError {
+    /// The share is not a solution of the current work.
+    Stale,
+    /// The share was already counted.
+    Duplicate,
+    /// The hash of the share has fewer leading zero bits than required.
+    InsufficientWork { required_bits: u32 },
+    /// The share meets the difficulty of the block, but the server refused
+    /// the block.
+    Block(SubmitError),
+}
+
+impl fmt::Display for ShareError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShareError::Stale => write!(f, "Stale share"),
+            ShareError::Duplicate => write!(f, "Duplicate share"),
+            ShareError::InsufficientWork { required_bits } => {
+                write!(f, "Insufficient work: {} leading zero bits required", required_bits)
+            }
+            ShareError::Block(e) => write!(f, "Block refused: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ShareError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ShareError::Block(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Answer of `GET /pool/stats`. Miners are identified by their hex encoded
+/// public key.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct PoolStats {
+    /// Public key of the pool, which mines its blocks.
+    pub public_key: String,
+    /// Difficulty of the shares of the current work.
+    pub share_difficulty: Option<u32>,
+    /// Shares of the current round by miner.
+    pub round_shares: BTreeMap<String, u64>,
+    /// Blocks the pool added to the chain.
+    pub blocks_found: u64,
+    /// Rewards earned by miners, not paid yet.
+    pub owed: BTreeMap<String, u64>,
+    /// Rewards sent to miners, whose transactions are not mined yet.
+    pub pending: BTreeMap<String, u64>,
+    /// Rewards paid to miners by transactions of the best chain.
+    pub paid: BTreeMap<String, u64>,
+}
+
+/// A transaction of the pool paying a miner.
+#[derive(Debug)]
+struct Payout {
+    miner: Vec<u8>,
+    amount: u64,
+    tx: Transaction,
+}
+
+/// The work of the pool, and what its block earns.
+#[derive(Debug)]
+struct CurrentWork {
+    work: PoolWork,
+    /// Reward of the block and fees of its transactions.
+    earnings: u64,
+}
+
+/// A mining pool on top of a [`BlockServer`].
+///
+/// The pool mines blocks signed with its own key, so that it earns their
+/// rewards, and hands them out as work. Miners send back solutions meeting a
+/// share difficulty below the one of the block, counted per miner. A round
+/// ends when a share also meets the difficulty of the block: the earnings of
+/// the block are split between the miners in proportion to their shares of
+/// the round, and paid by transactions from the pool.
+///
+/// A payout is pending until a block of the best chain includes it. If its
+/// transaction leaves the mempool unmined, the amount is owed again, and paid
+/// after the next round with the same nonce, so that only one of the two
+/// transactions can be mined. Payouts are only as final as the block
+/// including them.
+#[derive(Debug)]
+pub struct Pool {
+    key: SigningKey,
+    name: String,
+    share_offset: u32,
+    current: Option<CurrentWork>,
+    /// Hashes of the shares of the current work.
+    seen: HashSet<[u8; 32]>,
+    /// Shares of the current round by miner.
+    round: HashMap<Vec<u8>, u64>,
+    blocks_found: u64,
+    owed: HashMap<Vec<u8>, u64>,
+    /// Payouts waiting in the mempool.
+    pending: Vec<Payout>,
+    /// Payouts which left the mempool unmined, owed again, which could still
+    /// be mined while their nonce is not used.
+    dropped: Vec<Payout>,
+    paid: HashMap<Vec<u8>, u64>,
+}
+
+impl Pool {
+    /// Creates a pool mining blocks named `name`, signed with `key`, whose
+    /// shares have `share_offset` leading zero bits fewer than blocks.
+    pub fn new(key: SigningKey, name: &str, share_offset: u32) -> Self {
+        Pool {
+            key,
+            name: name.to_string(),
+            share_offset,
+            current: None,
+            seen: HashSet::new(),
+            round: HashMap::new(),
+            blocks_found: 0,
+            owed: HashMap::new(),
+            pending: Vec::new(),
+            dropped: Vec::new(),
+            paid: HashMap::new(),
+        }
+    }
+
+    /// The block the pool mines on top of the best tip of `server`, renewed
+    /// when the best tip changes.
+    pub fn work(&mut self, server: &BlockServer) -> Result<PoolWork, BlockValidationError> {
+        self.reconcile(server);
+        if let Some(current) = &self.current {
+            if current.work.block.parent_hash == server.chain().best_tip() {
+                return Ok(current.work.clone());
+            }
+        }
+        let template = server.template(&self.name)?;
+        let block = template.block(self.name.clone(), &self.key, DanceMove::Y);
+        let fees = block.transactions.iter().map(|tx| tx.fee).sum::<u64>();
+        let work = PoolWork {
+            share_difficulty: block.difficulty.saturating_sub(self.share_offset),
+            block,
+        };
+        self.seen.clear();
+        self.current = Some(CurrentWork {
+            earnings: template.reward.saturating_add(fees),
+            work: work.clone(),
+        });
+        Ok(work)
+    }
+
+    /// Counts a share of a miner. If it meets the difficulty of the block,
+    /// adds the block to `server` and ends the round.
+    pub fn submit_share(&mut self, server: &mut BlockServer, share: Share) -> Result<ShareStatus, ShareError> {
+        let Some(current) = &self.current else {
+            return Err(ShareError::Stale);
+        };
+        // Only the nonce differs from the work, which extends the best tip
+        let mut unsolved = share.block.clone();
+        unsolved.nonce = current.work.block.nonce;
+        if unsolved != current.work.block || unsolved.parent_hash != server.chain().best_tip() {
+            return Err(ShareError::Stale);
+        }
+
+        let hash = share.block.hash_block();
+        let required_bits = current.work.share_difficulty;
+        if !share.block.pow_check(&hash, required_bits) {
+            return Err(ShareError::InsufficientWork { required_bits });
+        }
+        if !self.seen.insert(hash) {
+            return Err(ShareError::Duplicate);
+        }
+        if !share.block.pow_check(&hash, share.block.difficulty) {
+            *self.round.entry(share.miner).or_default() += 1;
+            return Ok(ShareStatus::Accepted);
+        }
+
+        // A share whose block is refused earns nothing
+        let earnings = current.earnings;
+        let status = server.submit_block(share.block).map_err(ShareError::Block)?;
+        *self.round.entry(share.miner).or_default() += 1;
+        match status {
+            BlockStatus::Connected(_) => {
+                self.end_round(server, earnings);
+                Ok(ShareStatus::BlockFound)
+            }
+            BlockStatus::Orphaned | BlockStatus::Duplicate => Ok(ShareStatus::Accepted),
+        }
+    }
+
+    /// Splits `earnings` between the miners of the round, in proportion to
+    /// their shares, then pays what the pool owes. What the rounding leaves
+    /// stays with the pool.
+    fn end_round(&mut self, server: &mut BlockServer, earnings: u64) {
+        let total: u64 = self.round.values().sum();
+        for (miner, shares) in self.round.drain() {
+            let part = (u128::from(earnings) * u128::from(shares) / u128::from(total)) as u64;
+            *self.owed.entry(miner).or_default() += part;
+        }
+        self.blocks_found += 1;
+        self.current = None;
+        self.pay(server);
+    }
+
+    /// Sends the rewards owed to miners as transactions from the pool. What
+    /// cannot be paid yet, such as when the block of the pool is not on the
+    /// best chain, is paid after the next round.
+    fn pay(&mut self, server: &mut BlockServer) {
+        self.reconcile(server);
+        let pool_key = self.key.verifying_key().to_bytes();
+        let network_id = server.chain().rules().network_id().to_string();
+        let mut owed: Vec<(Vec<u8>, u64)> = self.owed.drain().filter(|(_, amount)| *amount > 0).collect();
+        owed.sort();
+        for (miner, amount) in owed {
+            let nonce = server.pending_account(&pool_key).nonce;
+            let tx = Transaction::transfer(&self.key, miner.clone(), amount, 0, nonce, &network_id);
+            match server.submit_transaction(tx.clone()) {
+                Ok(()) => self.pending.push(Payout { miner, amount, tx }),
+                Err(e) => {
+                    println!("Cannot pay {} to {}: {}", amount, hex::encode(&miner), e);
+                    self.owed.insert(miner, amount);
+                }
+            }
+        }
+    }
+
+    /// Follows the payouts through the best chain of `server`: the mined
+    /// ones are paid, the ones which left the mempool are owed again.
+    fn reconcile(&mut self, server: &BlockServer) {
+        let Some(since_nonce) = self.pending.iter().chain(&self.dropped).map(|payout| payout.tx.nonce).min() else {
+            return;
+        };
+        let pool_key = self.key.verifying_key().to_bytes();
+        let chain = server.chain();
+        let nonce = chain.ledger().account(&pool_key).nonce;
+
+        // The transactions of the pool from `since_nonce` on, newest first
+        let mut mined = HashSet::new();
+        let mut remaining = nonce.saturating_sub(since_nonce);
+        for block in chain.ancestors(chain.best_tip()) {
+            if remaining == 0 {
+                break;
+            }
+            for tx in block.transactions.iter().filter(|tx| tx.from == pool_key && tx.nonce >= since_nonce) {
+                mined.insert(tx.hash());
+                remaining -= 1;
+            }
+        }
+
+        for payout in std::mem::take(&mut self.pending) {
+            if mined.contains(&payout.tx.hash()) {
+                *self.paid.entry(payout.miner).or_default() += payout.amount;
+            } else if payout.tx.nonce >= nonce && server.mempool().contains(&payout.tx.hash()) {
+                self.pending.push(payout);
+            } else {
+                *self.owed.entry(payout.miner.clone()).or_default() += payout.amount;
+                // Another transaction may still take its nonce
+                if payout.tx.nonce >= nonce {
+                    self.dropped.push(payout);
+                }
+            }
+        }
+        // A dropped payout mined anyway was not owed anymore
+        for payout in std::mem::take(&mut self.dropped) {
+            if mined.contains(&payout.tx.hash()) {
+                let owed = self.owed.entry(payout.miner.clone()).or_default();
+                *owed = owed.saturating_sub(payout.amount);
+                *self.paid.entry(payout.miner).or_default() += payout.amount;
+            } else if payout.tx.nonce >= nonce {
+                self.dropped.push(payout);
+            }
+        }
+        self.owed.retain(|_, amount| *amount > 0);
+    }
+
+    /// The state of the pool, its payouts followed through the best chain of
+    /// `server`.
+    pub fn stats(&mut self, server: &BlockServer) -> PoolStats {
+        self.reconcile(server);
+        let by_miner = |map: &HashMap<Vec<u8>, u64>| map.iter().map(|(miner, n)| (hex::encode(miner), *n)).collect();
+        let mut pending = BTreeMap::new();
+        for payout in &self.pending {
+            *pending.entry(hex::encode(&payout.miner)).or_default() += payout.amount;
+        }
+        PoolStats {
+            public_key: hex::encode(self.key.verifying_key().to_bytes()),
+            share_difficulty: self.current.as_ref().map(|current| current.work.share_difficulty),
+            round_shares: by_miner(&self.round),
+            blocks_found: self.blocks_found,
+            owed: by_miner(&self.owed),
+            pending,
+            paid: by_miner(&self.paid),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use miner::block::CancelHandle;
+    use miner::chainspec::ChainSpec;
+    use std::fs;
+    use std::sync::Arc;
+
+    /// Solves the work, meeting `target` but not more, or the difficulty of
+    /// the block if `target` is None.
+    fn solve(work: &PoolWork, target: Option<u32>) -> miner::block::Block {
+        let mut block = work.block.clone();
+        loop {
+            let bits = target.unwrap_or(block.difficulty);
+            block.search_nonce(&mut rand::thread_rng(), bits, None, 1, &CancelHandle::new());
+            let hash = block.hash_block();
+            if target.is_none() || !block.pow_check(&hash, block.difficulty) {
+                return block;
+            }
+        }
+    }
+
+    #[test]
+    fn test_rounds() {
+        let dir = std::env::temp_dir().join(format!("pool-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut server = BlockServer::open(ChainSpec::create("testnet", 6), &dir).unwrap();
+        let mut pool = Pool::new(SigningKey::from_bytes(&[5; 32]), DEFAULT_POOL_NAME, 4);
+        let (alice, bob) = (vec![1; 32], vec![2; 32]);
+
+        let work = pool.work(&server).unwrap();
+        assert_eq!(work.share_difficulty, 2);
+        assert_eq!(pool.work(&server).unwrap(), work);
+
+        // Alice finds two shares, Bob one share then the block
+        for miner in [&alice, &alice, &bob] {
+            let block = solve(&work, Some(work.share_difficulty));
+            let share = Share { miner: miner.clone(), block };
+            assert!(matches!(pool.submit_share(&mut server, share.clone()), Ok(ShareStatus::Accepted)));
+            assert!(matches!(pool.submit_share(&mut server, share), Err(ShareError::Duplicate)));
+        }
+        let mut tampered = solve(&work, Some(work.share_difficulty));
+        tampered.miner = "bob".to_string();
+        let share = Share { miner: bob.clone(), block: tampered };
+        assert!(matches!(pool.submit_share(&mut server, share), Err(ShareError::Stale)));
+        assert_eq!(pool.stats(&server).round_shares[&hex::encode(&alice)], 2);
+
+        let share = Share { miner: bob.clone(), block: solve(&work, None) };
+        assert!(matches!(pool.submit_share(&mut server, share.clone()), Ok(ShareStatus::BlockFound)));
+        assert!(matches!(pool.submit_share(&mut server, share), Err(ShareError::Stale)));
+
+        // The reward is split by shares and sent by the pool
+        let reward = miner::ledger::block_reward(1);
+        let stats = pool.stats(&server);
+        assert_eq!(stats.blocks_found, 1);
+        assert!(stats.round_shares.is_empty());
+        assert_eq!(stats.pending[&hex::encode(&alice)], reward / 2);
+        assert_eq!(stats.pending[&hex::encode(&bob)], reward / 2);
+        assert!(stats.paid.is_empty());
+        assert_eq!(server.mempool().len(), 2);
+
+        // Then paid once the next block of the pool includes the payouts
+        let work = pool.work(&server).unwrap();
+        assert_eq!(work.block.transactions.len(), 2);
+        let share = Share { miner: alice.clone(), block: solve(&work, None) };
+        assert!(matches!(pool.submit_share(&mut server, share), Ok(ShareStatus::BlockFound)));
+        let stats = pool.stats(&server);
+        assert_eq!(stats.paid[&hex::encode(&alice)], reward / 2);
+        assert_eq!(stats.paid[&hex::encode(&bob)], reward / 2);
+        assert_eq!(stats.pending[&hex::encode(&alice)], miner::ledger::block_reward(2));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_dropped_payout() {
+        let dir = std::env::temp_dir().join(format!("pool-dropped-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut server = BlockServer::open(ChainSpec::create("testnet", 6), &dir).unwrap();
+        let pool_key = SigningKey::from_bytes(&[5; 32]);
+        let mut pool = Pool::new(pool_key.clone(), DEFAULT_POOL_NAME, 4);
+        let (alice, bob) = (vec![1; 32], vec![2; 32]);
+        let work = pool.work(&server).unwrap();
+        // One share each, Alice's first payout taking the first nonce
+        let share = Share { miner: bob.clone(), block: solve(&work, Some(work.share_difficulty)) };
+        pool.submit_share(&mut server, share).unwrap();
+        let share = Share { miner: alice.clone(), block: solve(&work, None) };
+        assert!(matches!(pool.submit_share(&mut server, share), Ok(ShareStatus::BlockFound)));
+
+        // Another transaction of the pool takes the nonce of Alice's payout
+        let network_id = server.chain().rules().network_id().to_string();
+        let other = Transaction::transfer(&pool_key, vec![3; 32], 1, 0, 0, &network_id);
+        let miner_key = SigningKey::from_bytes(&[6; 32]);
+        let mut block = server.template("miner").unwrap().block("miner".to_string(), &miner_key, DanceMove::Y);
+        block.set_transactions(vec![other]);
+        block.sign(&miner_key);
+        block.search_nonce(&mut rand::thread_rng(), block.difficulty, None, 1, &CancelHandle::new());
+        assert!(matches!(server.submit_block(block), Ok(BlockStatus::Connected(_))));
+
+        // Alice is owed her reward again, Bob's payout still applies
+        let reward = miner::ledger::block_reward(1);
+        let stats = pool.stats(&server);
+        assert_eq!(stats.owed[&hex::encode(&alice)], reward / 2);
+        assert_eq!(stats.pending[&hex::encode(&bob)], reward / 2);
+        assert!(!stats.pending.contains_key(&hex::encode(&alice)));
+        assert!(stats.paid.is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_refused_block_share() {
+        let dir = std::env::temp_dir().join(format!("pool-refused-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        // The server only accepts blocks harder than the ones of the chain
+        let spec = ChainSpec::create("testnet", 6);
+        let mut server = BlockServer::open_with_rules(spec.genesis.clone(), 8, &dir, Arc::new(spec)).unwrap();
+        let mut pool = Pool::new(SigningKey::from_bytes(&[5; 32]), DEFAULT_POOL_NAME, 4);
+        let (alice, bob) = (vec![1; 32], vec![2; 32]);
+        let work = pool.work(&server).unwrap();
+
+        let share = Share { miner: alice.clone(), block: solve(&work, Some(work.share_difficulty)) };
+        assert!(matches!(pool.submit_share(&mut server, share), Ok(ShareStatus::Accepted)));
+        let share = Share { miner: bob.clone(), block: solve(&work, None) };
+        assert!(matches!(
+            pool.submit_share(&mut server, share),
+            Err(ShareError::Block(SubmitError::Invalid(BlockValidationError::DifficultyTooLow { .. })))
+        ));
+
+        let stats = pool.stats(&server);
+        assert_eq!(stats.round_shares.len(), 1);
+        assert_eq!(stats.round_shares[&hex::encode(&alice)], 1);
+        assert_eq!(stats.blocks_found, 0);
+        assert!(stats.owed.is_empty() && stats.pending.is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,162 @@
+use miner::block::Block;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Name of the block log in the data directory.
+const LOG_FILE: &str = "blocks.log";
+/// Size of a record header: payload length and CRC32 of the payload.
+const HEADER_LEN: usize = 8;
+
+/// Append-only log of the blocks accepted by the server.
+///
+/// Each record is the length of the payload (u32, big endian), the CRC32 of
+/// the payload (u32, big endian) and the payload, the JSON encoding of a
+/// block. A crash while appending leaves a truncated or corrupted last record,
+/// which is dropped when opening the log.
+pub struct BlockLog {
+    file: File,
+}
+
+impl BlockLog {
+    /// Opens the log stored in `dir`, creating both if needed, and returns the
+    /// blocks it holds in the order they were appended. Everything after the
+    /// first invalid record is truncated.
+    pub fn open(dir: &Path) -> io::Result<(Self, Vec<Block>)> {
+        fs::create_dir_all(dir)?;
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(dir.join(LOG_FILE))?;
+
+        let mut content = Vec::new();
+        file.read_to_end(&mut content)?;
+
+        let mut blocks = Vec::new();
+        let mut offset = 0;
+        while let Some((block, len)) = read_record(&content[offset..]) {
+            blocks.push(block);
+            offset += len;
+        }
+
+        if offset < content.len() {
+            eprintln!(
+                "Dropping {} bytes of incomplete or corrupted records at the end of the block log",
+                content.len() - offset
+            );
+            file.set_len(offset as u64)?;
+            file.sync_all()?;
+        }
+        file.seek(SeekFrom::End(0))?;
+
+        Ok((BlockLog { file }, blocks))
+    }
+
+    /// Appends a block to the log, and waits for it to reach the disk
+    pub fn append(&mut self, block: &Block) -> io::Result<()> {
+        let payload = serde_json::to_vec(block)?;
+        let mut record = Vec::with_capacity(HEADER_LEN + payload.len());
+        record.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        record.extend_from_slice(&crc32fast::hash(&payload).to_be_bytes());
+        record.extend_from_slice(&payload);
+        self.file.write_all(&record)?;
+        self.file.sync_data()
+    }
+}
+
+/// Decodes the record at the start of `bytes`, returning the block and the
+/// length of the record. Returns None if the record is incomplete or invalid.
+fn read_record(bytes: &[u8]) -> Option<(Block, usize)> {
+    let header = bytes.get(..HEADER_LEN)?;
+    let len = u32::from_be_bytes(header[..4].try_into().unwrap()) as usize;
+    let checksum = u32::from_be_bytes(header[4..].try_into().unwrap());
+    let payload = bytes.get(HEADER_LEN..HEADER_LEN + len)?;
+    if crc32fast::hash(payload) != checksum {
+        return None;
+    }
+    let block = serde_json::from_slice(payload).ok()?;
+    Some((block, HEADER_LEN + len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use miner::block::DanceMove;
+    use std::path::PathBuf;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("block-log-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn test_block(nonce: u64) -> Block {
+        Block::new(vec![1, 2, 3], "miner".to_string(), nonce, DanceMove::M)
+    }
+
+    #[test]
+    fn test_append_and_replay() {
+        let dir = test_dir("replay");
+        let (mut log, blocks) = BlockLog::open(&dir).unwrap();
+        assert!(blocks.is_empty());
+        log.append(&test_block(1)).unwrap();
+        log.append(&test_block(2)).unwrap();
+        drop(log);
+
+        let (mut log, blocks) = BlockLog::open(&dir).unwrap();
+        assert_eq!(blocks, vec![test_block(1), test_block(2)]);
+        log.append(&test_block(3)).unwrap();
+        drop(log);
+
+        let (_, blocks) = BlockLog::open(&dir).unwrap();
+        assert_eq!(blocks, vec![test_block(1), test_block(2), test_block(3)]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_truncated_tail() {
+        let dir = test_dir("truncated");
+        let (mut log, _) = BlockLog::open(&dir).unwrap();
+        log.append(&test_block(1)).unwrap();
+        log.append(&test_block(2)).unwrap();
+        drop(log);
+
+        // Simulate a crash in the middle of the last record
+        let path = dir.join(LOG_FILE);
+        let len = fs::metadata(&path).unwrap().len();
+        fs::OpenOptions::new().write(true).open(&path).unwrap().set_len(len - 5).unwrap();
+
+        let (mut log, blocks) = BlockLog::open(&dir).unwrap();
+        assert_eq!(blocks, vec![test_block(1)]);
+
+        // New records go after the last valid one
+        log.append(&test_block(3)).unwrap();
+        drop(log);
+        let (_, blocks) = BlockLog::open(&dir).unwrap();
+        assert_eq!(blocks, vec![test_block(1), test_block(3)]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_corrupted_record() {
+        let dir = test_dir("corrupted");
+        let (mut log, _) = BlockLog::open(&dir).unwrap();
+        log.append(&test_block(1)).unwrap();
+        log.append(&test_block(2)).unwrap();
+        drop(log);
+
+        // Flip a byte of the last payload
+        let path = dir.join(LOG_FILE);
+        let mut content = fs::read(&path).unwrap();
+        let last = content.len() - 2;
+        content[last] ^= 0xFF;
+        fs::write(&path, &content).unwrap();
+
+        let (_, blocks) = BlockLog::open(&dir).unwrap();
+        assert_eq!(blocks, vec![test_block(1)]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}